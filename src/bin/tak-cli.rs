@@ -0,0 +1,116 @@
+//! Plays a game read from stdin and prints how it ends up, for scripting and
+//! testing the engine without a window.
+//!
+//! Each line is either a TPS position, which replaces the game so far but
+//! keeps `--komi`, or PTN moves, which are played from it. Afterwards the
+//! board and result are printed, and with `--suggest` the bot's move.
+
+use std::{
+    io::{self, Read},
+    process::ExitCode,
+};
+
+use tak::{
+    bot::{EvalWeights, best_move},
+    engine::{AnnotatedMove, GameState, WinRules},
+};
+
+const USAGE: &str = "usage: tak-cli [--size N] [--komi HALF_FLATS] [--suggest [DEPTH]]";
+
+/// How many turns ahead `--suggest` looks unless told otherwise, the same as
+/// "Bot Move" in the game
+const BOT_DEPTH: u32 = 2;
+
+struct Options {
+    size: usize,
+    komi: i32,
+    suggest: Option<u32>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        size: 5,
+        komi: 0,
+        suggest: None,
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{name} needs a value\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--size" => {
+                let size = value("--size")?;
+                options.size = size.parse().map_err(|_| format!("bad size '{size}'"))?;
+            }
+            "--komi" => {
+                let komi = value("--komi")?;
+                options.komi = komi.parse().map_err(|_| format!("bad komi '{komi}'"))?;
+            }
+            "--suggest" => {
+                let depth = args.next_if(|depth| !depth.starts_with("--"));
+                options.suggest = Some(match depth {
+                    Some(depth) => depth.parse().map_err(|_| format!("bad depth '{depth}'"))?,
+                    None => BOT_DEPTH,
+                });
+            }
+            _ => return Err(format!("unknown argument '{arg}'\n{USAGE}")),
+        }
+    }
+    Ok(options)
+}
+
+/// Plays every line of `input` in turn, starting from an empty board
+fn play(input: &str, options: &Options) -> Result<GameState, String> {
+    let mut state = GameState::try_with_win_rules(options.size, options.komi, WinRules::default())
+        .map_err(|err| err.to_string())?;
+    for line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if line.contains('/') && !line.contains('.') {
+            state = GameState::from_tps(line)
+                .map_err(|err| format!("{line}: {err}"))?
+                .with_rules(options.komi, WinRules::default());
+            continue;
+        }
+        let moves = AnnotatedMove::parse_list(line).map_err(|err| format!("{line}: {err}"))?;
+        for played in moves {
+            state
+                .apply_ptn(&played.ptn)
+                .map_err(|err| format!("{}: {err}", played.ptn))?;
+        }
+    }
+    Ok(state)
+}
+
+fn run() -> Result<(), String> {
+    let options = parse_args(std::env::args().skip(1))?;
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|err| err.to_string())?;
+    let state = play(&input, &options)?;
+
+    println!("{state}");
+    println!(
+        "result: {}",
+        state.result().to_ptn_result().unwrap_or("ongoing")
+    );
+    if let Some(depth) = options.suggest {
+        let size = state.board().size();
+        match best_move(&mut state.clone(), depth, &EvalWeights::default()) {
+            Some(turn) => println!("suggest: {}", turn.to_ptn(size)),
+            None => println!("suggest: none"),
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("tak-cli: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
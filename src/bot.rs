@@ -0,0 +1,720 @@
+//! A negamax bot that works with any game implementing `Position`, so that
+//! rule variants can reuse the search without touching it.
+
+use std::{
+    cmp::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::engine::{GameResult, GameState, Loc, Player, StoneType, Turn};
+
+mod transposition;
+
+pub use transposition::{Bound, Entry, TranspositionTable};
+
+/// A game position the bot can search
+pub trait Position {
+    type Move: Clone + PartialEq;
+    /// Tuning parameters for `evaluate`
+    type Weights;
+
+    /// Every legal move for the player to move
+    fn legal_moves(&self) -> Vec<Self::Move>;
+
+    /// Plays `mv`, which must be one of `legal_moves`
+    fn apply(&mut self, mv: &Self::Move);
+
+    /// Takes back the last move passed to `apply`
+    fn undo(&mut self);
+
+    fn is_terminal(&self) -> bool;
+
+    /// How good the position is for the player to move. Higher is better.
+    fn evaluate(&self, weights: &Self::Weights) -> i32;
+
+    /// Sorts `moves` so the most promising come first, which lets the search
+    /// prune more. Leaves them as they are by default.
+    fn order_moves(&self, _moves: &mut [Self::Move]) {}
+
+    /// Which of two moves with the same score to play, `Less` meaning `a`, so
+    /// the bot picks the same move whatever order they were searched in. The
+    /// one searched first by default.
+    fn tie_break(&self, _a: &Self::Move, _b: &Self::Move) -> Ordering {
+        Ordering::Equal
+    }
+
+    /// Whether `mv` lets the opponent win with their reply. The search won't
+    /// play such a move unless every move does. Never by default.
+    fn gives_opponent_win(&self, _mv: &Self::Move) -> bool {
+        false
+    }
+
+    /// Identifies the position however it was reached, so the search can
+    /// reuse what it learned about it
+    fn key(&self) -> u64;
+}
+
+/// Score for a position that has been won
+pub const WIN: i32 = 1_000_000;
+
+/// Deepest `best_move_timed` will search, however much time it has
+const MAX_DEPTH: u32 = 64;
+
+/// Entries in the transposition table of each search
+const TABLE_SIZE: usize = 1 << 16;
+
+/// Searches `depth` moves ahead and returns the best move for the player to
+/// move, or `None` if there are no legal moves
+pub fn best_move<P: Position>(
+    position: &mut P,
+    depth: u32,
+    weights: &P::Weights,
+) -> Option<P::Move> {
+    let mut table = TranspositionTable::new(TABLE_SIZE);
+    let mut search = Search::new(weights, None, &mut table);
+    match search.root(position, depth) {
+        Ok(best) => best.map(|(mv, _)| mv),
+        Err(OutOfTime) => unreachable!("searches without a deadline don't run out of time"),
+    }
+}
+
+/// Searches one move deeper at a time until `budget` runs out, and returns
+/// the best move from the deepest search that finished. Always returns a
+/// move if there is one, even if not even the shallowest search finished, and
+/// then one that doesn't hand the opponent a win if it can.
+pub fn best_move_timed<P: Position>(
+    position: &mut P,
+    budget: Duration,
+    weights: &P::Weights,
+) -> Option<P::Move> {
+    let mut table = TranspositionTable::new(TABLE_SIZE);
+    let mut search = Search::new(weights, Some(Instant::now() + budget), &mut table);
+    let moves = search.moves(position);
+    let mut best = moves
+        .iter()
+        .find(|mv| !position.gives_opponent_win(mv))
+        .or(moves.first())?
+        .clone();
+    for depth in 1..=MAX_DEPTH {
+        match search.root(position, depth) {
+            Ok(Some((mv, score))) => {
+                best = mv;
+                if score >= WIN {
+                    break;
+                }
+            }
+            Ok(None) | Err(OutOfTime) => break,
+        }
+    }
+    Some(best)
+}
+
+/// The search passed its deadline before finishing
+struct OutOfTime;
+
+struct Search<'a, P: Position> {
+    weights: &'a P::Weights,
+    deadline: Option<Instant>,
+    /// Keyed by `Position::key`, and kept between iterations of
+    /// `best_move_timed` so each one starts with what the previous one found
+    table: &'a mut TranspositionTable<P::Move>,
+}
+
+impl<'a, P: Position> Search<'a, P> {
+    fn new(
+        weights: &'a P::Weights,
+        deadline: Option<Instant>,
+        table: &'a mut TranspositionTable<P::Move>,
+    ) -> Self {
+        Search {
+            weights,
+            deadline,
+            table,
+        }
+    }
+
+    /// The legal moves in `position`, with the best one found so far first
+    fn moves(&self, position: &P) -> Vec<P::Move> {
+        let mut moves = position.legal_moves();
+        position.order_moves(&mut moves);
+        if let Some(best) = self
+            .table
+            .probe(position.key())
+            .and_then(|entry| entry.best.as_ref())
+            && let Some(i) = moves.iter().position(|mv| mv == best)
+        {
+            moves[..=i].rotate_right(1);
+        }
+        moves
+    }
+
+    fn check_deadline(&self) -> Result<(), OutOfTime> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(OutOfTime);
+        }
+        Ok(())
+    }
+
+    /// The best move and its score, or `None` if there are no legal moves
+    fn root(&mut self, position: &mut P, depth: u32) -> Result<Option<(P::Move, i32)>, OutOfTime> {
+        let mut moves = self.moves(position);
+        // Shallow searches can't see the reply coming, so don't leave it to
+        // them. Checking every move takes a while, so the deadline still counts.
+        let mut safe = Vec::new();
+        for mv in &moves {
+            self.check_deadline()?;
+            if !position.gives_opponent_win(mv) {
+                safe.push(mv.clone());
+            }
+        }
+        if !safe.is_empty() {
+            moves = safe;
+        }
+        let mut best = None;
+        let mut alpha = -i32::MAX;
+        for mv in moves {
+            position.apply(&mv);
+            // One wider than needed, so a move as good as the best so far gets
+            // an exact score rather than a bound, and the tie can be broken
+            let beta = alpha.saturating_sub(1).saturating_neg();
+            let score = self.negamax(position, depth.saturating_sub(1), -i32::MAX, beta);
+            position.undo();
+            let score = -score?;
+            let better = match &best {
+                None => true,
+                Some(best) => {
+                    score > alpha || (score == alpha && position.tie_break(&mv, best).is_lt())
+                }
+            };
+            if better {
+                alpha = score;
+                best = Some(mv);
+            }
+        }
+        if let Some(mv) = &best {
+            self.table.store(Entry {
+                key: position.key(),
+                depth,
+                bound: Bound::Exact,
+                score: alpha,
+                best: Some(mv.clone()),
+            });
+        }
+        Ok(best.map(|mv| (mv, alpha)))
+    }
+
+    fn negamax(
+        &mut self,
+        position: &mut P,
+        depth: u32,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> Result<i32, OutOfTime> {
+        self.check_deadline()?;
+        if depth == 0 || position.is_terminal() {
+            return Ok(position.evaluate(self.weights));
+        }
+        let key = position.key();
+        let original_alpha = alpha;
+        if let Some(entry) = self.table.probe(key).filter(|entry| entry.depth >= depth) {
+            match entry.bound {
+                Bound::Exact => return Ok(entry.score),
+                Bound::Lower => alpha = alpha.max(entry.score),
+                Bound::Upper => beta = beta.min(entry.score),
+            }
+            if alpha >= beta {
+                return Ok(entry.score);
+            }
+        }
+        let moves = self.moves(position);
+        if moves.is_empty() {
+            return Ok(position.evaluate(self.weights));
+        }
+        let mut best = -i32::MAX;
+        let mut best_move = None;
+        for mv in moves {
+            position.apply(&mv);
+            let score = self.negamax(position, depth - 1, -beta, -alpha);
+            position.undo();
+            let score = -score?;
+            if score > best {
+                best = score;
+                best_move = Some(mv);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        let bound = if best <= original_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.store(Entry {
+            key,
+            depth,
+            bound,
+            score: best,
+            best: best_move,
+        });
+        Ok(best)
+    }
+}
+
+/// How much each feature of a Tak position is worth to the player that has it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalWeights {
+    /// Per flat counted over the opponent's
+    pub flat_diff: i32,
+    /// For being able to complete a road next turn
+    pub road_threat: i32,
+    /// Per stone buried under stacks the player controls
+    pub stack_control: i32,
+    /// Per controlled square, scaled by how close it is to the center
+    pub center_influence: i32,
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights {
+            flat_diff: 100,
+            road_threat: 50,
+            stack_control: 10,
+            center_influence: 5,
+        }
+    }
+}
+
+impl EvalWeights {
+    /// The weighted score of `player`'s features in `state`, not counting
+    /// their opponent's
+    fn score(&self, state: &GameState, player: Player) -> i32 {
+        let board = state.board();
+        let size = board.size();
+        let mut stack_control = 0;
+        for i in 0..size * size {
+            let loc = Loc::from_index(i, size);
+            if board[loc].last().is_some_and(|top| top.owner == player) {
+                stack_control += board.stack_height(loc) as i32 - 1;
+            }
+        }
+        self.flat_diff * board.flat_count(player) as i32
+            + self.road_threat * i32::from(state.is_tak(player))
+            + self.stack_control * stack_control
+            + self.center_influence * board.positional_scores(player).center
+    }
+}
+
+/// Puts turns that complete a road first, then flat placements, then other
+/// placements, then spreads
+pub fn order_moves(state: &GameState, moves: &mut [Turn]) {
+    let wins = state.road_threat_moves(state.current_player());
+    moves.sort_by_key(|turn| match turn {
+        _ if wins.contains(turn) => 0,
+        Turn::Place {
+            loc: _,
+            player: _,
+            typ: StoneType::Flat,
+        } => 1,
+        Turn::Place {
+            loc: _,
+            player: _,
+            typ: _,
+        } => 2,
+        Turn::Move {
+            loc: _,
+            player: _,
+            dir: _,
+            total: _,
+            drops: _,
+        } => 3,
+    });
+}
+
+impl Position for GameState {
+    type Move = Turn;
+    type Weights = EvalWeights;
+
+    fn legal_moves(&self) -> Vec<Turn> {
+        self.legal_turns()
+    }
+
+    fn apply(&mut self, mv: &Turn) {
+        self.apply_turn(mv);
+    }
+
+    fn undo(&mut self) {
+        GameState::undo(self);
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.result().is_over()
+    }
+
+    fn evaluate(&self, weights: &EvalWeights) -> i32 {
+        let me = self.current_player();
+        match self.result() {
+            GameResult::RoadWin(winner)
+            | GameResult::FlatWin(winner)
+            | GameResult::TimeoutWin(winner)
+            | GameResult::ResignationWin(winner) => {
+                if winner == me {
+                    WIN
+                } else {
+                    -WIN
+                }
+            }
+            GameResult::Draw => 0,
+            GameResult::Ongoing => weights.score(self, me) - weights.score(self, me.next()),
+        }
+    }
+
+    fn order_moves(&self, moves: &mut [Turn]) {
+        order_moves(self, moves);
+    }
+
+    /// Flats, then walls, then capstones, then spreads, so that a tie doesn't
+    /// spend a capstone for nothing, and then by PTN
+    fn tie_break(&self, a: &Turn, b: &Turn) -> Ordering {
+        let rank = |turn: &Turn| match turn {
+            Turn::Place {
+                loc: _,
+                player: _,
+                typ,
+            } => match typ {
+                StoneType::Flat => 0,
+                StoneType::Standing => 1,
+                StoneType::Capstone => 2,
+            },
+            Turn::Move {
+                loc: _,
+                player: _,
+                dir: _,
+                total: _,
+                drops: _,
+            } => 3,
+        };
+        let size = self.board().size();
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.to_ptn(size).cmp(&b.to_ptn(size)))
+    }
+
+    fn gives_opponent_win(&self, mv: &Turn) -> bool {
+        GameState::gives_opponent_win(self, mv)
+    }
+
+    fn key(&self) -> u64 {
+        self.zobrist()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Players alternate taking one or two stones, and whoever takes the last
+    /// stone wins
+    struct Nim {
+        stones: u32,
+        taken: Vec<u32>,
+    }
+
+    impl Position for Nim {
+        type Move = u32;
+        type Weights = ();
+
+        fn legal_moves(&self) -> Vec<u32> {
+            (1..=self.stones.min(2)).collect()
+        }
+
+        fn apply(&mut self, mv: &u32) {
+            self.stones -= mv;
+            self.taken.push(*mv);
+        }
+
+        fn undo(&mut self) {
+            self.stones += self.taken.pop().unwrap();
+        }
+
+        fn is_terminal(&self) -> bool {
+            self.stones == 0
+        }
+
+        fn evaluate(&self, _weights: &()) -> i32 {
+            // The player to move has nothing left to take, so they lost
+            if self.stones == 0 { -WIN } else { 0 }
+        }
+
+        fn key(&self) -> u64 {
+            self.stones.into()
+        }
+    }
+
+    #[test]
+    fn best_move_on_mock_position() {
+        // Leaving a multiple of three stones wins
+        for (stones, expected) in [(4, 1), (5, 2), (7, 1), (8, 2)] {
+            let mut nim = Nim {
+                stones,
+                taken: Vec::new(),
+            };
+            assert_eq!(best_move(&mut nim, 8, &()), Some(expected));
+            assert_eq!(nim.stones, stones);
+        }
+        let mut empty = Nim {
+            stones: 0,
+            taken: Vec::new(),
+        };
+        assert_eq!(best_move(&mut empty, 3, &()), None);
+    }
+
+    #[test]
+    fn best_move_takes_road_win() {
+        let mut state = GameState::new(3);
+        // The first two are swapped, so White has a3 and a2
+        for (row, col) in [(0, 2), (0, 0), (1, 0), (1, 2)] {
+            assert!(state.apply_turn(&Turn::Place {
+                loc: Loc { row, col },
+                player: state.current_player(),
+                typ: StoneType::Flat,
+            }));
+        }
+        assert_eq!(
+            best_move(&mut state, 2, &EvalWeights::default()),
+            Some(Turn::Place {
+                loc: Loc { row: 2, col: 0 },
+                player: Player::White,
+                typ: StoneType::Flat,
+            })
+        );
+    }
+
+    /// Counts the moves the search plays, optionally without ordering them
+    struct Counting {
+        state: GameState,
+        ordered: bool,
+        applied: usize,
+    }
+
+    impl Position for Counting {
+        type Move = Turn;
+        type Weights = EvalWeights;
+
+        fn legal_moves(&self) -> Vec<Turn> {
+            self.state.legal_moves()
+        }
+
+        fn apply(&mut self, mv: &Turn) {
+            self.applied += 1;
+            self.state.apply(mv);
+        }
+
+        fn undo(&mut self) {
+            self.state.undo();
+        }
+
+        fn is_terminal(&self) -> bool {
+            self.state.is_terminal()
+        }
+
+        fn evaluate(&self, weights: &EvalWeights) -> i32 {
+            self.state.evaluate(weights)
+        }
+
+        fn order_moves(&self, moves: &mut [Turn]) {
+            if self.ordered {
+                self.state.order_moves(moves);
+            }
+        }
+
+        fn key(&self) -> u64 {
+            self.state.key()
+        }
+    }
+
+    #[test]
+    fn ordering_finds_win_with_fewer_moves() {
+        // White completes the first rank with e1
+        let tps = "x5/x,2,x3/x2,2,x2/x,2,x3/1,1,1,1,x 1 5";
+        let win = Turn::from_ptn("e1", Player::White, 5).unwrap();
+        let mut applied = Vec::new();
+        for ordered in [false, true] {
+            let mut counting = Counting {
+                state: GameState::from_tps(tps).unwrap(),
+                ordered,
+                applied: 0,
+            };
+            let best = best_move(&mut counting, 2, &EvalWeights::default());
+            assert_eq!(best, Some(win.clone()));
+            applied.push(counting.applied);
+        }
+        assert!(applied[1] < applied[0], "{applied:?}");
+    }
+
+    /// Searches its moves in either order
+    struct Reordered {
+        state: GameState,
+        reversed: bool,
+    }
+
+    impl Position for Reordered {
+        type Move = Turn;
+        type Weights = EvalWeights;
+
+        fn legal_moves(&self) -> Vec<Turn> {
+            self.state.legal_moves()
+        }
+
+        fn apply(&mut self, mv: &Turn) {
+            self.state.apply(mv);
+        }
+
+        fn undo(&mut self) {
+            Position::undo(&mut self.state);
+        }
+
+        fn is_terminal(&self) -> bool {
+            self.state.is_terminal()
+        }
+
+        fn evaluate(&self, weights: &EvalWeights) -> i32 {
+            self.state.evaluate(weights)
+        }
+
+        fn order_moves(&self, moves: &mut [Turn]) {
+            self.state.order_moves(moves);
+            if self.reversed {
+                moves.reverse();
+            }
+        }
+
+        fn tie_break(&self, a: &Turn, b: &Turn) -> Ordering {
+            self.state.tie_break(a, b)
+        }
+
+        fn key(&self) -> u64 {
+            self.state.key()
+        }
+    }
+
+    #[test]
+    fn ties_are_broken_the_same_way() {
+        // The corners, and the edges, of an empty board are all as good as
+        // each other
+        for depth in 1..=2 {
+            let best: Vec<Option<Turn>> = [false, true]
+                .into_iter()
+                .map(|reversed| {
+                    let mut position = Reordered {
+                        state: GameState::new(4),
+                        reversed,
+                    };
+                    best_move(&mut position, depth, &EvalWeights::default())
+                })
+                .collect();
+            assert!(best[0].is_some());
+            assert_eq!(best[0], best[1], "depth {depth}");
+        }
+    }
+
+    #[test]
+    fn timed_search_returns_move_on_tiny_budget() {
+        let mut state = GameState::from_ptn_moves(6, 0, &["a1", "f6", "c3", "d4"]).unwrap();
+        let before = state.clone();
+        let start = Instant::now();
+        let best = best_move_timed(&mut state, Duration::ZERO, &EvalWeights::default()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(state, before);
+        assert!(state.valid_turn(&best));
+
+        // Even without time to search, it doesn't leave Black's road open
+        let mut state = GameState::from_tps("x5/x5/x5/x5/2,2,2,2,x 1 5").unwrap();
+        let best = best_move_timed(&mut state, Duration::ZERO, &EvalWeights::default()).unwrap();
+        assert_eq!(best.squares(), [Loc { row: 4, col: 4 }]);
+    }
+
+    #[test]
+    fn timed_search_finds_road_win() {
+        let mut state = GameState::from_tps("x5/x,2,x3/x2,2,x2/x,2,x3/1,1,1,1,x 1 5").unwrap();
+        let best = best_move_timed(
+            &mut state,
+            Duration::from_millis(200),
+            &EvalWeights::default(),
+        );
+        assert_eq!(best, Turn::from_ptn("e1", Player::White, 5).ok());
+
+        let mut nim = Nim {
+            stones: 7,
+            taken: Vec::new(),
+        };
+        assert_eq!(
+            best_move_timed(&mut nim, Duration::from_millis(200), &()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn no_capstone_from_empty_reserve() {
+        // White's only capstone is already on the board
+        let tps = "x5/x,2,x3/x2,1C,x2/x,2,x3/x5 1 3";
+        for depth in 1..=2 {
+            let mut state = GameState::from_tps(tps).unwrap();
+            let best = best_move(&mut state, depth, &EvalWeights::default()).unwrap();
+            assert!(state.valid_turn(&best));
+            assert!(!matches!(
+                best,
+                Turn::Place {
+                    loc: _,
+                    player: _,
+                    typ: StoneType::Capstone,
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn blocks_a_road_it_cant_see_coming() {
+        // Black takes a1-e1 next turn unless White blocks e1. Searching one move
+        // deep without a road threat weight, nothing else would warn it.
+        let tps = "x5/x5/x5/x5/2,2,2,2,x 1 5";
+        let weights = EvalWeights {
+            road_threat: 0,
+            ..EvalWeights::default()
+        };
+        let mut state = GameState::from_tps(tps).unwrap();
+        let best = best_move(&mut state, 1, &weights).unwrap();
+        assert!(
+            best.squares() == [Loc { row: 4, col: 4 }],
+            "{}",
+            best.to_ptn(5)
+        );
+    }
+
+    #[test]
+    fn road_threat_weight() {
+        // White can either capture d3 with the stack on c3 for an extra flat, or
+        // place on d1 or e1 to threaten a road along the first rank
+        let tps = "x5/2,x4/x2,11,2,x/x4,2/1,1,1,x2 1 5";
+        let capture = Turn::from_ptn("c3>", Player::White, 5).unwrap();
+        let threats = ["d1", "e1"].map(|ptn| Turn::from_ptn(ptn, Player::White, 5).unwrap());
+
+        let mut state = GameState::from_tps(tps).unwrap();
+        assert_eq!(
+            best_move(&mut state, 1, &EvalWeights::default()),
+            Some(capture)
+        );
+        let weights = EvalWeights {
+            road_threat: 10_000,
+            ..EvalWeights::default()
+        };
+        let best = best_move(&mut state, 1, &weights).unwrap();
+        assert!(threats.contains(&best));
+    }
+}
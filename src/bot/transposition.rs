@@ -0,0 +1,103 @@
+//! A fixed-size table of what searches have found out about positions, keyed
+//! by their hash.
+
+/// How a stored score relates to the position's true score
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The true score is at least this, because the search stopped early
+    /// after finding a move this good
+    Lower,
+    /// The true score is at most this, because no move was any better
+    Upper,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry<M> {
+    pub key: u64,
+    /// How many moves ahead the position was searched
+    pub depth: u32,
+    pub bound: Bound,
+    pub score: i32,
+    pub best: Option<M>,
+}
+
+/// Each key has a single slot, shared with every other key that maps to it.
+/// A slot keeps whichever entry was searched deepest, so that shallow
+/// searches late in the tree don't push out expensive results near the root.
+pub struct TranspositionTable<M> {
+    slots: Vec<Option<Entry<M>>>,
+}
+
+impl<M> TranspositionTable<M> {
+    /// A table holding at most `size` entries, which must be at least 1
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a transposition table needs at least one slot");
+        TranspositionTable {
+            slots: std::iter::repeat_with(|| None).take(size).collect(),
+        }
+    }
+
+    fn slot(&self, key: u64) -> usize {
+        (key % self.slots.len() as u64) as usize
+    }
+
+    /// The entry stored for `key`, if it hasn't been replaced
+    pub fn probe(&self, key: u64) -> Option<&Entry<M>> {
+        self.slots[self.slot(key)]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+    }
+
+    /// Stores `entry`, unless its slot holds a deeper search of another
+    /// position
+    pub fn store(&mut self, entry: Entry<M>) {
+        let slot = self.slot(entry.key);
+        let replace = match &self.slots[slot] {
+            None => true,
+            Some(old) => old.key == entry.key || old.depth <= entry.depth,
+        };
+        if replace {
+            self.slots[slot] = Some(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u64, depth: u32) -> Entry<u32> {
+        Entry {
+            key,
+            depth,
+            bound: Bound::Exact,
+            score: 10 * depth as i32,
+            best: Some(depth),
+        }
+    }
+
+    #[test]
+    fn probe_returns_stored_entry() {
+        let mut table = TranspositionTable::new(16);
+        assert_eq!(table.probe(42), None);
+        table.store(entry(42, 3));
+        assert_eq!(table.probe(42), Some(&entry(42, 3)));
+        // A shallower search of the same position still replaces it
+        table.store(entry(42, 1));
+        assert_eq!(table.probe(42), Some(&entry(42, 1)));
+    }
+
+    #[test]
+    fn collisions_keep_deeper_entry() {
+        let mut table = TranspositionTable::new(16);
+        table.store(entry(5, 4));
+        // 21 shares 5's slot
+        table.store(entry(21, 2));
+        assert_eq!(table.probe(21), None);
+        assert_eq!(table.probe(5), Some(&entry(5, 4)));
+        table.store(entry(21, 4));
+        assert_eq!(table.probe(21), Some(&entry(21, 4)));
+        assert_eq!(table.probe(5), None);
+    }
+}
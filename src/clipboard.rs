@@ -0,0 +1,38 @@
+//! Copies text to the system clipboard, so positions can be pasted into other
+//! Tak tools. The web build uses the browser's clipboard; native builds need
+//! the `clipboard` feature.
+
+/// Puts `text` on the clipboard. On the web the browser may still refuse it
+/// afterwards, which is only logged.
+#[cfg(target_arch = "wasm32")]
+pub fn copy(text: &str) -> Result<(), String> {
+    let window = web_sys::window().ok_or("there is no browser window")?;
+    let written = window.navigator().clipboard().write_text(text);
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(err) = wasm_bindgen_futures::JsFuture::from(written).await {
+            bevy::log::warn!("The browser refused to copy to the clipboard: {err:?}");
+        }
+    });
+    Ok(())
+}
+
+/// Puts `text` on the clipboard
+#[cfg(all(not(target_arch = "wasm32"), feature = "clipboard"))]
+pub fn copy(text: &str) -> Result<(), String> {
+    use std::sync::Mutex;
+
+    // On Linux, copied text is only there for as long as the clipboard it was
+    // copied with, so it's kept rather than made for each copy
+    static CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+    let mut clipboard = CLIPBOARD.lock().map_err(|err| err.to_string())?;
+    let clipboard = match &mut *clipboard {
+        Some(clipboard) => clipboard,
+        None => clipboard.insert(arboard::Clipboard::new().map_err(|err| err.to_string())?),
+    };
+    clipboard.set_text(text).map_err(|err| err.to_string())
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "clipboard")))]
+pub fn copy(_text: &str) -> Result<(), String> {
+    Err("this build has no clipboard support".to_owned())
+}
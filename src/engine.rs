@@ -1,11 +1,30 @@
 use std::{
-    collections::HashMap,
+    collections::{HashSet, VecDeque},
     fmt,
     ops::{Index, IndexMut},
+    time::Duration,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use rand::{Rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+mod clock;
+mod encoding;
+pub mod history;
+pub mod notation;
+mod roads;
+pub mod symmetry;
+mod tinue;
+mod zobrist;
+
+pub use clock::Clock;
+pub use notation::{AnnotatedMove, ReplayError};
+use roads::Roads;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Player {
+    /// Moves first unless the game was set up otherwise
+    #[default]
     White,
     Black,
 }
@@ -20,15 +39,107 @@ impl fmt::Display for Player {
 }
 
 impl Player {
+    /// Every player, in turn order
+    pub fn all() -> [Player; 2] {
+        [Self::White, Self::Black]
+    }
+
+    /// Where the player is in `all`
+    fn index(self) -> usize {
+        match self {
+            Self::White => 0,
+            Self::Black => 1,
+        }
+    }
+
+    /// Who moves after this player
     pub fn next(self) -> Player {
+        let all = Self::all();
+        all[(self.index() + 1) % all.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameResult {
+    Ongoing,
+    RoadWin(Player),
+    FlatWin(Player),
+    /// The other player ran out of time
+    TimeoutWin(Player),
+    /// The other player resigned
+    ResignationWin(Player),
+    Draw,
+}
+
+impl GameResult {
+    pub fn is_over(self) -> bool {
+        !matches!(self, Self::Ongoing)
+    }
+
+    pub fn winner(self) -> Option<Player> {
         match self {
-            Self::White => Self::Black,
-            Self::Black => Self::White,
+            Self::RoadWin(player)
+            | Self::FlatWin(player)
+            | Self::TimeoutWin(player)
+            | Self::ResignationWin(player) => Some(player),
+            Self::Ongoing | Self::Draw => None,
+        }
+    }
+
+    /// How the game in `state`, whose result this is, ended, e.g. "White wins
+    /// by a road from a1 to a5" or "Black wins on flats 13-12 (komi 2)", with
+    /// each player called by `name`. Flat counts include komi.
+    pub fn describe<'a>(self, state: &GameState, name: impl Fn(Player) -> &'a str) -> String {
+        let board = state.board();
+        // In whole flats, and halves where needed
+        let white = f64::from(board.flat_count(Player::White) as u32);
+        let black = f64::from(board.flat_count(Player::Black) as u32) + f64::from(state.komi) / 2.;
+        let komi = f64::from(state.komi) / 2.;
+        match self {
+            Self::Ongoing => "The game isn't over".to_owned(),
+            Self::RoadWin(player) => match board.winning_road(player).as_deref() {
+                Some([first, .., last]) => {
+                    // Lower rank first for a road between the first and last
+                    // ranks, otherwise lower file first
+                    let ranks = first.row.abs_diff(last.row) == board.size() - 1;
+                    let backwards = if ranks {
+                        first.row < last.row
+                    } else {
+                        first.col > last.col
+                    };
+                    let (from, to) = if backwards {
+                        (last, first)
+                    } else {
+                        (first, last)
+                    };
+                    format!(
+                        "{} wins by a road from {} to {}",
+                        name(player),
+                        from.to_algebraic(board.size()),
+                        to.to_algebraic(board.size())
+                    )
+                }
+                _ => format!("{} wins by a road", name(player)),
+            },
+            Self::FlatWin(player) => {
+                let (winner, loser) = match player {
+                    Player::White => (white, black),
+                    Player::Black => (black, white),
+                };
+                format!(
+                    "{} wins on flats {winner}-{loser} (komi {komi})",
+                    name(player)
+                )
+            }
+            Self::TimeoutWin(player) => format!("{} wins on time", name(player)),
+            Self::ResignationWin(player) => format!("{} wins by resignation", name(player)),
+            Self::Draw if state.win_rules.roads_only => "Draw, as only roads win".to_owned(),
+            Self::Draw => format!("Draw on flats {white}-{black} (komi {komi})"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Dir {
     North,
     East,
@@ -36,17 +147,41 @@ pub enum Dir {
     West,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Dir {
+    pub const ALL: [Dir; 4] = [Dir::North, Dir::East, Dir::South, Dir::West];
+}
+
+/// A square on the board. Row 0 is the top row and column 0 the leftmost
+/// column, as the board is displayed. Note that PTN numbers ranks from the
+/// bottom, so row 0 is rank `size` there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Loc {
     pub row: usize,
     pub col: usize,
 }
 
 impl Loc {
+    /// The square at position `index` when reading the board left to right,
+    /// top to bottom (the order the UI lays out tiles in)
+    pub fn from_index(index: usize, size: usize) -> Loc {
+        Loc {
+            row: index / size,
+            col: index % size,
+        }
+    }
+
+    /// Inverse of `Loc::from_index`
+    pub fn to_index(self, size: usize) -> usize {
+        self.row * size + self.col
+    }
+
+    /// Moves `count` squares in `dir`. Moving off the north or west edge wraps
+    /// around to a huge index rather than panicking, so the result should be
+    /// checked with `Board::valid_loc`.
     pub fn move_in_by(&self, dir: Dir, count: usize) -> Loc {
         match dir {
             Dir::North => Loc {
-                row: self.row - count,
+                row: self.row.wrapping_sub(count),
                 col: self.col,
             },
             Dir::East => Loc {
@@ -59,7 +194,7 @@ impl Loc {
             },
             Dir::West => Loc {
                 row: self.row,
-                col: self.col - count,
+                col: self.col.wrapping_sub(count),
             },
         }
     }
@@ -69,7 +204,7 @@ impl Loc {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StoneType {
     Flat,
     Standing,
@@ -86,10 +221,10 @@ impl fmt::Display for StoneType {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Stone {
-    owner: Player,
-    typ: StoneType,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stone {
+    pub owner: Player,
+    pub typ: StoneType,
 }
 
 impl fmt::Display for Stone {
@@ -98,7 +233,7 @@ impl fmt::Display for Stone {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Turn {
     Place {
         loc: Loc,
@@ -131,14 +266,123 @@ impl Turn {
             } => *player,
         }
     }
+
+    /// Every square the turn changes: where a stone is placed, or where a
+    /// spread starts followed by where it drops stones
+    pub fn squares(&self) -> Vec<Loc> {
+        match self {
+            Self::Place {
+                loc,
+                player: _,
+                typ: _,
+            } => vec![*loc],
+            Self::Move {
+                loc,
+                player: _,
+                dir,
+                total: _,
+                drops,
+            } => (0..=drops.len())
+                .map(|distance| loc.move_in_by(*dir, distance))
+                .collect(),
+        }
+    }
+}
+
+/// Variations on how games are won. The default is the standard rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WinRules {
+    /// Filling the board or running out of pieces is a draw rather than a win
+    /// for whoever has more flats, for teaching games where only roads should
+    /// matter
+    pub roads_only: bool,
+    /// A turn may not complete the opponent's road without also completing
+    /// the mover's, where standard Tak lets the mover lose that way. Only
+    /// spreads can do it, by uncovering the opponent's stones.
+    pub no_suicide: bool,
+}
+
+/// Why a turn can't be played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnError {
+    GameOver,
+    /// The turn is for the player who isn't to move
+    WrongPlayer,
+    /// Each player's first turn has to place a flat
+    OpeningNotFlat,
+    /// The player has no regular stones left to place a flat or wall
+    NoStones,
+    NoCapstones,
+    /// The square is taken, or the spread breaks the rules for picking up
+    /// and dropping stones
+    IllegalOnBoard,
+    /// Under `WinRules::no_suicide`, the turn would lose by completing the
+    /// opponent's road
+    CompletesOpponentsRoad,
+}
+
+impl fmt::Display for TurnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GameOver => write!(f, "the game is already over"),
+            Self::WrongPlayer => write!(f, "it is the other player's turn"),
+            Self::OpeningNotFlat => write!(f, "each player's first turn has to place a flat"),
+            Self::NoStones => write!(f, "there are no stones left to place"),
+            Self::NoCapstones => write!(f, "there are no capstones left to place"),
+            Self::IllegalOnBoard => write!(f, "the board doesn't allow it"),
+            Self::CompletesOpponentsRoad => write!(f, "it would complete the opponent's road"),
+        }
+    }
+}
+
+impl std::error::Error for TurnError {}
+
+/// A board size that isn't played, given by `GameState::try_new`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeError(pub usize);
+
+impl fmt::Display for SizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "board size {} is not between 3 and 8", self.0)
+    }
+}
+
+impl std::error::Error for SizeError {}
+
+/// The direction a road runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    /// West to east
+    Horizontal,
+    /// North to south
+    Vertical,
+    /// Separate roads, or one that crosses, in both directions
+    Both,
+}
+
+#[derive(Debug, Clone)]
+pub struct Board {
+    /// The stacks of every square in row-major order, so that a board is one
+    /// allocation plus one per stack
+    stacks: Vec<Vec<Stone>>,
+    /// Which squares have changed since the last `take_dirty`, so that a
+    /// renderer only has to redraw those. A new board is all dirty.
+    dirty: Vec<bool>,
+}
+
+/// Boards are equal when their stones are, whatever has been redrawn
+impl PartialEq for Board {
+    fn eq(&self, other: &Self) -> bool {
+        self.stacks == other.stacks
+    }
 }
 
-#[derive(Debug)]
-struct Board(Vec<Vec<Vec<Stone>>>);
+impl Eq for Board {}
 
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for row in &self.0 {
+        for row in self.stacks.chunks(self.size()) {
             for stack in row {
                 if stack.len() == 0 {
                     write!(f, "x")?
@@ -157,25 +401,485 @@ impl fmt::Display for Board {
 
 impl Board {
     fn new(size: usize) -> Self {
-        Board(
-            std::iter::repeat_with(|| {
-                std::iter::repeat_with(|| Vec::new())
-                    .take(size.into())
-                    .collect()
-            })
-            .take(size.into())
-            .collect(),
-        )
+        Board {
+            stacks: std::iter::repeat_with(Vec::new).take(size * size).collect(),
+            dirty: vec![true; size * size],
+        }
+    }
+
+    /// The squares that changed since this was last called, in row-major
+    /// order, marking them all clean
+    pub fn take_dirty(&mut self) -> Vec<Loc> {
+        let size = self.size();
+        let dirty = (0..size * size)
+            .filter(|&index| self.dirty[index])
+            .map(|index| Loc::from_index(index, size))
+            .collect();
+        self.dirty.fill(false);
+        dirty
+    }
+
+    pub fn size(&self) -> usize {
+        self.stacks.len().isqrt()
+    }
+
+    pub fn stack_height(&self, loc: Loc) -> usize {
+        self[loc].len()
+    }
+
+    /// The top stone at `loc` as one character for compact dumps: `f`, `s`
+    /// and `c` for White's flats, walls and capstones, capitals for Black's,
+    /// and `.` for an empty square
+    pub fn top_char(&self, loc: Loc) -> char {
+        let Some(top) = self[loc].last() else {
+            return '.';
+        };
+        let ch = match top.typ {
+            StoneType::Flat => 'f',
+            StoneType::Standing => 's',
+            StoneType::Capstone => 'c',
+        };
+        match top.owner {
+            Player::White => ch,
+            Player::Black => ch.to_ascii_uppercase(),
+        }
+    }
+
+    /// Who owns each stone at `loc`, bottom to top
+    pub fn stack_owners(&self, loc: Loc) -> Vec<Player> {
+        self[loc].iter().map(|stone| stone.owner).collect()
+    }
+
+    /// The type of each stone at `loc`, bottom to top. Only the top can be
+    /// anything but a flat.
+    pub fn stack_types(&self, loc: Loc) -> Vec<StoneType> {
+        self[loc].iter().map(|stone| stone.typ).collect()
+    }
+
+    /// How many stones `player` may pick up from `loc`: none unless they own
+    /// the top stone, otherwise the whole stack up to `carry_limit`
+    pub fn max_pickup(&self, loc: Loc, player: Player, carry_limit: usize) -> usize {
+        match self[loc].last() {
+            Some(top) if top.owner == player => self[loc].len().min(carry_limit),
+            _ => 0,
+        }
+    }
+
+    /// Every spread of the capstone on top of `loc`, if `player` has one
+    /// there, including those that end by crushing a wall. Spreads of other
+    /// stones can't crush, so their targets are just the legal moves.
+    pub fn capstone_targets(&self, loc: Loc, player: Player, carry_limit: usize) -> Vec<Turn> {
+        let top = self[loc].last();
+        if !(top.is_some_and(|top| top.owner == player && top.typ == StoneType::Capstone)) {
+            return Vec::new();
+        }
+        spread_turns(self, loc, player, carry_limit)
+    }
+
+    /// Puts `stone` on top of the stack at `loc`, without checking any rules.
+    /// Meant for setting up scenarios, not for playing turns.
+    #[cfg(test)]
+    pub(crate) fn place_stone(&mut self, loc: Loc, stone: Stone) {
+        self[loc].push(stone);
     }
 
-    fn size(&self) -> usize {
-        self.0.len()
+    /// Replaces the stack at `loc` (listed bottom to top), without checking
+    /// any rules
+    pub(crate) fn set_stack(&mut self, loc: Loc, stack: Vec<Stone>) {
+        self[loc] = stack;
     }
 
     fn valid_loc(&self, loc: Loc) -> bool {
         loc.row < self.size() && loc.col < self.size()
     }
 
+    /// Whether every square has at least one stone. Walls and capstones fill a
+    /// square just like flats do.
+    fn is_full(&self) -> bool {
+        self.stacks.iter().all(|stack| !stack.is_empty())
+    }
+
+    fn empty_count(&self) -> usize {
+        self.stacks.iter().filter(|stack| stack.is_empty()).count()
+    }
+
+    /// Every square with no stones on it, in row-major order
+    pub fn empty_squares(&self) -> Vec<Loc> {
+        let size = self.size();
+        self.stacks
+            .iter()
+            .enumerate()
+            .filter(|(_, stack)| stack.is_empty())
+            .map(|(i, _)| Loc::from_index(i, size))
+            .collect()
+    }
+
+    /// Every stone on the board with its square and how far up its stack it
+    /// is, 0 being the bottom. Squares are in row-major order, each stack
+    /// from the bottom up.
+    pub fn all_stones(&self) -> impl Iterator<Item = (Loc, usize, Stone)> + '_ {
+        let size = self.size();
+        self.stacks.iter().enumerate().flat_map(move |(i, stack)| {
+            let loc = Loc::from_index(i, size);
+            stack
+                .iter()
+                .enumerate()
+                .map(move |(layer, stone)| (loc, layer, *stone))
+        })
+    }
+
+    /// Number of squares topped by a flat belonging to `player`. Walls and
+    /// capstones don't count towards a flat win.
+    pub fn flat_count(&self, player: Player) -> usize {
+        self.stacks
+            .iter()
+            .filter_map(|stack| stack.last())
+            .filter(|stone| stone.owner == player && matches!(stone.typ, StoneType::Flat))
+            .count()
+    }
+
+    /// Number of flats, walls and capstones belonging to `player` on top of a
+    /// stack, in that order
+    pub fn top_type_counts(&self, player: Player) -> (u32, u32, u32) {
+        let mut counts = (0, 0, 0);
+        for stone in self.stacks.iter().filter_map(|stack| stack.last()) {
+            if stone.owner != player {
+                continue;
+            }
+            match stone.typ {
+                StoneType::Flat => counts.0 += 1,
+                StoneType::Standing => counts.1 += 1,
+                StoneType::Capstone => counts.2 += 1,
+            }
+        }
+        counts
+    }
+
+    /// Number of `player`'s stones under the top of a stack, which can only
+    /// move when the stack's owner moves them
+    pub fn buried_counts(&self, player: Player) -> u32 {
+        self.stacks
+            .iter()
+            .filter_map(|stack| stack.split_last())
+            .flat_map(|(_, buried)| buried)
+            .filter(|stone| stone.owner == player)
+            .count() as u32
+    }
+
+    /// Number of `player`'s stones buried under one of the opponent's walls
+    /// or capstones. Nothing can be stacked on a capstone, and on a wall only
+    /// by a capstone crushing it, so covering the stack won't win those
+    /// stones back. Stones under an opponent's flat are left out on purpose,
+    /// as any spread onto the stack can still take it over.
+    pub fn buried_under_opponent(&self, player: Player) -> u32 {
+        self.stacks
+            .iter()
+            .filter_map(|stack| stack.split_last())
+            .filter(|(top, _)| top.owner != player && !matches!(top.typ, StoneType::Flat))
+            .flat_map(|(_, buried)| buried)
+            .filter(|stone| stone.owner == player)
+            .count() as u32
+    }
+
+    /// How many regular stones and capstones `player` has on the board,
+    /// including buried ones
+    fn pieces_on_board(&self, player: Player) -> (usize, usize) {
+        let mut reg = 0;
+        let mut cap = 0;
+        for stone in self.stacks.iter().flatten() {
+            if stone.owner != player {
+                continue;
+            }
+            match stone.typ {
+                StoneType::Flat | StoneType::Standing => reg += 1,
+                StoneType::Capstone => cap += 1,
+            }
+        }
+        (reg, cap)
+    }
+
+    /// Whether any of `player`'s capstones is on the board, on top of a stack
+    /// or not
+    pub fn has_capstone(&self, player: Player) -> bool {
+        self.pieces_on_board(player).1 > 0
+    }
+
+    /// How much of `player`'s control of the board is near the center and how
+    /// much near the edges, counting every square they have a stone on top of
+    pub fn positional_scores(&self, player: Player) -> PositionalScores {
+        let size = self.size();
+        let edge = size as i32 - 1;
+        let mut scores = PositionalScores {
+            center: 0,
+            edges: 0,
+        };
+        for index in 0..size * size {
+            let loc = Loc::from_index(index, size);
+            if !self[loc].last().is_some_and(|top| top.owner == player) {
+                continue;
+            }
+            let (row, col) = (loc.row as i32, loc.col as i32);
+            let from_center = (2 * row - edge).abs() + (2 * col - edge).abs();
+            scores.center += 2 * edge - from_center;
+            let from_edge = row.min(col).min(edge - row).min(edge - col);
+            scores.edges += edge / 2 - from_edge;
+        }
+        scores
+    }
+
+    /// How strongly each player controls each square, in row-major order,
+    /// positive for White and negative for Black. Every stack pushes on its
+    /// own square and its neighbours with as many stones as its owner could
+    /// pick up from it, so tall stacks dominate the squares around them.
+    pub fn influence_map(&self) -> Vec<i32> {
+        let size = self.size();
+        let mut map = vec![0; size * size];
+        for index in 0..size * size {
+            let loc = Loc::from_index(index, size);
+            let Some(top) = self[loc].last() else {
+                continue;
+            };
+            let carry = self.stack_height(loc).min(size) as i32;
+            let strength = match top.owner {
+                Player::White => carry,
+                Player::Black => -carry,
+            };
+            map[index] += strength;
+            for dir in Dir::ALL {
+                if let Some(next) = self.neighbor(loc, dir) {
+                    map[next.to_index(size)] += strength;
+                }
+            }
+        }
+        map
+    }
+
+    /// Whether the top stone at `loc` belongs to `player` and can be part of a
+    /// road (flats and capstones can, walls can't)
+    fn is_road_piece(&self, loc: Loc, player: Player) -> bool {
+        self[loc]
+            .last()
+            .is_some_and(|stone| stone.owner == player && !matches!(stone.typ, StoneType::Standing))
+    }
+
+    /// Whether `player` has a road connecting opposite edges of the board,
+    /// either north to south or west to east. Roads are made of flats and
+    /// capstones, a wall breaks a road even if it belongs to `player`.
+    fn check_road(&self, player: Player) -> bool {
+        self.road_axis(player).is_some()
+    }
+
+    /// The squares of one of `player`'s roads, in order from one edge to the
+    /// other, or `None` if they don't have one
+    pub fn winning_road(&self, player: Player) -> Option<Vec<Loc>> {
+        let size = self.size();
+        let north_edge = (0..size).map(|col| Loc { row: 0, col });
+        let west_edge = (0..size).map(|row| Loc { row, col: 0 });
+        self.road_path(player, north_edge, |loc| loc.row == size - 1)
+            .or_else(|| self.road_path(player, west_edge, |loc| loc.col == size - 1))
+    }
+
+    /// Like `connects`, but returns the squares on a shortest way through
+    fn road_path(
+        &self,
+        player: Player,
+        start: impl Iterator<Item = Loc>,
+        is_goal: impl Fn(Loc) -> bool,
+    ) -> Option<Vec<Loc>> {
+        let size = self.size();
+        let mut came_from: Vec<Option<Option<Loc>>> = vec![None; size * size];
+        let mut frontier = VecDeque::new();
+        for loc in start.filter(|loc| self.is_road_piece(*loc, player)) {
+            came_from[loc.to_index(size)] = Some(None);
+            frontier.push_back(loc);
+        }
+        while let Some(loc) = frontier.pop_front() {
+            if is_goal(loc) {
+                let mut path = vec![loc];
+                while let Some(Some(previous)) = came_from[path.last().unwrap().to_index(size)] {
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for dir in Dir::ALL {
+                let Some(next) = self.neighbor(loc, dir) else {
+                    continue;
+                };
+                if came_from[next.to_index(size)].is_none() && self.is_road_piece(next, player) {
+                    came_from[next.to_index(size)] = Some(Some(loc));
+                    frontier.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Which way `player`'s roads run, if they have any
+    pub fn road_axis(&self, player: Player) -> Option<Axis> {
+        let size = self.size();
+        let north_edge = (0..size).map(|col| Loc { row: 0, col });
+        let west_edge = (0..size).map(|row| Loc { row, col: 0 });
+        let vertical = self.connects(player, north_edge, |loc| loc.row == size - 1);
+        let horizontal = self.connects(player, west_edge, |loc| loc.col == size - 1);
+        match (horizontal, vertical) {
+            (true, true) => Some(Axis::Both),
+            (true, false) => Some(Axis::Horizontal),
+            (false, true) => Some(Axis::Vertical),
+            (false, false) => None,
+        }
+    }
+
+    /// Every road piece of `player` connected to some edge of the board
+    /// through other road pieces, i.e. all the squares that could end up in
+    /// one of their roads without being moved
+    pub fn road_relevant_squares(&self, player: Player) -> HashSet<Loc> {
+        let size = self.size();
+        let mut frontier: Vec<Loc> = (0..size * size)
+            .map(|index| Loc::from_index(index, size))
+            .filter(|loc| {
+                let on_edge = Dir::ALL
+                    .iter()
+                    .any(|&dir| self.distance_to_edge(*loc, dir) == 0);
+                on_edge && self.is_road_piece(*loc, player)
+            })
+            .collect();
+        let mut relevant: HashSet<Loc> = frontier.iter().copied().collect();
+        while let Some(loc) = frontier.pop() {
+            for dir in Dir::ALL {
+                let Some(next) = self.neighbor(loc, dir) else {
+                    continue;
+                };
+                if self.is_road_piece(next, player) && relevant.insert(next) {
+                    frontier.push(next);
+                }
+            }
+        }
+        relevant
+    }
+
+    /// The fewest stones `player` would have to place to complete a road,
+    /// without moving any, or `None` if walls and the opponent's stones cut
+    /// every road off. Zero means they already have one.
+    pub fn shortest_road_distance(&self, player: Player) -> Option<u32> {
+        let size = self.size();
+        let north_edge = (0..size).map(|col| Loc { row: 0, col });
+        let west_edge = (0..size).map(|row| Loc { row, col: 0 });
+        let vertical = self.placements_to_connect(player, north_edge, |loc| loc.row == size - 1);
+        let horizontal = self.placements_to_connect(player, west_edge, |loc| loc.col == size - 1);
+        vertical.into_iter().chain(horizontal).min()
+    }
+
+    /// The fewest empty squares that need filling to join a square in
+    /// `start` to one satisfying `is_goal` through `player`'s road pieces.
+    /// Searched breadth first, with road pieces searched before empty squares
+    /// since they cost nothing to pass through.
+    fn placements_to_connect(
+        &self,
+        player: Player,
+        start: impl Iterator<Item = Loc>,
+        is_goal: impl Fn(Loc) -> bool,
+    ) -> Option<u32> {
+        let size = self.size();
+        let cost = |loc: Loc| {
+            if self.is_road_piece(loc, player) {
+                Some(0)
+            } else if self[loc].is_empty() {
+                Some(1)
+            } else {
+                None
+            }
+        };
+        let mut best = vec![u32::MAX; size * size];
+        let mut frontier = VecDeque::new();
+        for loc in start {
+            let Some(cost) = cost(loc) else {
+                continue;
+            };
+            best[loc.to_index(size)] = cost;
+            // Keep the frontier sorted, so that road pieces on the edge come first
+            if cost == 0 {
+                frontier.push_front((loc, cost));
+            } else {
+                frontier.push_back((loc, cost));
+            }
+        }
+        while let Some((loc, distance)) = frontier.pop_front() {
+            if distance > best[loc.to_index(size)] {
+                continue;
+            }
+            if is_goal(loc) {
+                return Some(distance);
+            }
+            for dir in Dir::ALL {
+                let Some(next) = self.neighbor(loc, dir) else {
+                    continue;
+                };
+                let Some(cost) = cost(next) else {
+                    continue;
+                };
+                let next_distance = distance + cost;
+                if next_distance < best[next.to_index(size)] {
+                    best[next.to_index(size)] = next_distance;
+                    if cost == 0 {
+                        frontier.push_front((next, next_distance));
+                    } else {
+                        frontier.push_back((next, next_distance));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Flood fills from the road pieces of `player` in `start`, returning
+    /// whether any square satisfying `is_goal` is reached.
+    fn connects(
+        &self,
+        player: Player,
+        start: impl Iterator<Item = Loc>,
+        is_goal: impl Fn(Loc) -> bool,
+    ) -> bool {
+        let mut visited = vec![vec![false; self.size()]; self.size()];
+        let mut frontier: Vec<Loc> = start
+            .filter(|loc| self.is_road_piece(*loc, player))
+            .collect();
+        for loc in &frontier {
+            visited[loc.row][loc.col] = true;
+        }
+        while let Some(loc) = frontier.pop() {
+            if is_goal(loc) {
+                return true;
+            }
+            for dir in Dir::ALL {
+                let Some(next) = self.neighbor(loc, dir) else {
+                    continue;
+                };
+                if !visited[next.row][next.col] && self.is_road_piece(next, player) {
+                    visited[next.row][next.col] = true;
+                    frontier.push(next);
+                }
+            }
+        }
+        false
+    }
+
+    /// How many squares there are between `loc` and the edge of the board in `dir`
+    fn distance_to_edge(&self, loc: Loc, dir: Dir) -> usize {
+        match dir {
+            Dir::North => loc.row,
+            Dir::East => self.size() - 1 - loc.col,
+            Dir::South => self.size() - 1 - loc.row,
+            Dir::West => loc.col,
+        }
+    }
+
+    /// The square adjacent to `loc` in `dir`, if it is on the board
+    fn neighbor(&self, loc: Loc, dir: Dir) -> Option<Loc> {
+        let next = loc.move_in(dir);
+        self.valid_loc(next).then_some(next)
+    }
+
     fn valid_turn(&self, turn: &Turn) -> bool {
         match turn {
             Turn::Place {
@@ -190,15 +894,18 @@ impl Board {
                 total,
                 drops,
             } => {
+                // Starts on the board
+                if !(self.valid_loc(*loc)) {
+                    return false;
+                }
                 // Drops is nonempty
-                if !(drops.len() > 0) {
+                if drops.is_empty() {
                     return false;
                 }
                 // Total is at most the carry limit
                 if !(*total <= self.size()) {
                     return false;
                 }
-                // Total is less than is there
                 // Doesn't pick up more than is there
                 if !(*total <= self[*loc].len()) {
                     return false;
@@ -207,8 +914,8 @@ impl Board {
                 if !(drops.iter().sum::<usize>() == *total) {
                     return false;
                 }
-                // Starts on the board
-                if !(self.valid_loc(*loc)) {
+                // Every drop leaves at least one stone
+                if drops.contains(&0) {
                     return false;
                 }
                 // Doesn't leave the board
@@ -220,13 +927,17 @@ impl Board {
                 if !(top_here.owner == *player) {
                     return false;
                 }
-                // Only the capstone (alone) can crush walls, nothing can stack capstones
+                // Only the capstone (alone, as the final drop) can crush walls,
+                // nothing can stack capstones
                 let mut next_loc = *loc;
-                for drop in drops {
+                for (i, drop) in drops.iter().enumerate() {
                     next_loc = next_loc.move_in(*dir);
+                    let is_last = i == drops.len() - 1;
                     if let Some(top_there) = self[next_loc].last() {
                         if matches!(top_there.typ, StoneType::Standing)
-                            && !(matches!(top_here.typ, StoneType::Capstone) && *drop == 1)
+                            && !(matches!(top_here.typ, StoneType::Capstone)
+                                && is_last
+                                && *drop == 1)
                         {
                             return false;
                         }
@@ -240,12 +951,24 @@ impl Board {
         }
     }
 
-    fn apply_turn(&mut self, turn: &Turn) {
+    /// Applies `turn` without checking it, for search code that already knows
+    /// it's legal, returning the location of the wall that was crushed by a
+    /// capstone, if any. That is what `undo_move_unchecked` needs to take it
+    /// back.
+    ///
+    /// The caller guarantees the board allows `turn`, e.g. because it came
+    /// from `GameState::legal_turns` or passed `GameState::check_turn` for
+    /// this position. Anything else may panic or leave the board in a state
+    /// no game can reach. `GameState::apply_turn` is the checked path.
+    pub fn apply_move_unchecked(&mut self, turn: &Turn) -> Option<Loc> {
         match turn {
-            Turn::Place { loc, player, typ } => self[*loc].push(Stone {
-                owner: *player,
-                typ: *typ,
-            }),
+            Turn::Place { loc, player, typ } => {
+                self[*loc].push(Stone {
+                    owner: *player,
+                    typ: *typ,
+                });
+                None
+            }
             Turn::Move {
                 loc,
                 player: _,
@@ -253,6 +976,7 @@ impl Board {
                 total,
                 drops,
             } => {
+                let crushed = self.would_crush(turn);
                 let stack_here = &mut self[*loc];
                 let mut held_stack = stack_here.split_off(stack_here.len() - total);
                 let mut next_loc = *loc;
@@ -260,12 +984,75 @@ impl Board {
                 for drop in drops {
                     next_loc = next_loc.move_in(*dir);
                     let new_held_stack = held_stack.split_off(*drop);
-                    if let Some(stack_top) = self[next_loc].last_mut() {
-                        stack_top.typ = StoneType::Flat
+                    // Covered flats are flat already, and nothing may cover a
+                    // capstone, so only a crushed wall changes
+                    if let Some(stack_top) = self[next_loc].last_mut()
+                        && stack_top.typ == StoneType::Standing
+                    {
+                        debug_assert_eq!(crushed, Some(next_loc), "only a crush flattens a wall");
+                        stack_top.typ = StoneType::Flat;
                     }
                     self[next_loc].append(&mut held_stack);
                     held_stack = new_held_stack;
                 }
+                crushed
+            }
+        }
+    }
+
+    /// The wall `turn` would flatten, assuming it is legal. Only a capstone
+    /// moving on its own can do that, as the last drop of a spread.
+    pub fn would_crush(&self, turn: &Turn) -> Option<Loc> {
+        let Turn::Move {
+            loc,
+            player: _,
+            dir,
+            total: _,
+            drops,
+        } = turn
+        else {
+            return None;
+        };
+        (1..=drops.len())
+            .map(|distance| loc.move_in_by(*dir, distance))
+            .filter(|drop_loc| self.valid_loc(*drop_loc))
+            .find(|drop_loc| {
+                self[*drop_loc]
+                    .last()
+                    .is_some_and(|top| matches!(top.typ, StoneType::Standing))
+            })
+    }
+
+    /// Reverts `turn`, which must have been the last turn applied. `crushed`
+    /// is the value `apply_move_unchecked` returned for it.
+    pub fn undo_move_unchecked(&mut self, turn: &Turn, crushed: Option<Loc>) {
+        match turn {
+            Turn::Place {
+                loc,
+                player: _,
+                typ: _,
+            } => {
+                self[*loc].pop();
+            }
+            Turn::Move {
+                loc,
+                player: _,
+                dir,
+                total: _,
+                drops,
+            } => {
+                let mut held_stack = Vec::new();
+                for (i, drop) in drops.iter().enumerate().rev() {
+                    let drop_loc = loc.move_in_by(*dir, i + 1);
+                    let stack_there = &mut self[drop_loc];
+                    let mut picked_up = stack_there.split_off(stack_there.len() - drop);
+                    picked_up.append(&mut held_stack);
+                    held_stack = picked_up;
+                    if crushed == Some(drop_loc) {
+                        self[drop_loc].last_mut().unwrap().typ = StoneType::Standing;
+                    }
+                }
+                self[*loc].append(&mut held_stack);
             }
         }
     }
@@ -275,124 +1062,1266 @@ impl Index<Loc> for Board {
     type Output = Vec<Stone>;
 
     fn index(&self, index: Loc) -> &Self::Output {
-        &self.0[index.row as usize][index.col as usize]
+        assert!(self.valid_loc(index), "{index:?} is off the board");
+        &self.stacks[index.to_index(self.size())]
     }
 }
 
+/// Marks the square dirty, as the only way to change a stack is through here
 impl IndexMut<Loc> for Board {
     fn index_mut(&mut self, index: Loc) -> &mut Self::Output {
-        &mut self.0[index.row as usize][index.col as usize]
+        assert!(self.valid_loc(index), "{index:?} is off the board");
+        let index = index.to_index(self.size());
+        self.dirty[index] = true;
+        &mut self.stacks[index]
+    }
+}
+
+/// The pieces a player has left to place
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reserve {
+    /// Stones, which can be placed as flats or walls
+    pub reg: u8,
+    pub cap: u8,
+}
+
+impl Reserve {
+    /// Each player's pieces at the start of a game, `None` for sizes that
+    /// aren't played
+    fn starting(size: usize) -> Option<Reserve> {
+        match size {
+            3 => Some(Reserve { reg: 10, cap: 0 }),
+            4 => Some(Reserve { reg: 15, cap: 0 }),
+            5 => Some(Reserve { reg: 21, cap: 1 }),
+            6 => Some(Reserve { reg: 30, cap: 1 }),
+            7 => Some(Reserve { reg: 40, cap: 2 }),
+            8 => Some(Reserve { reg: 50, cap: 2 }),
+            _ => None,
+        }
+    }
+}
+
+/// Every player's reserve, indexed by player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reserves([Reserve; 2]);
+
+impl Reserves {
+    /// Everyone starting with `reserve`
+    fn new(reserve: Reserve) -> Reserves {
+        Reserves(Player::all().map(|_| reserve))
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Reserve> {
+        self.0.iter()
+    }
+}
+
+impl Index<Player> for Reserves {
+    type Output = Reserve;
+
+    fn index(&self, player: Player) -> &Reserve {
+        &self.0[player.index()]
+    }
+}
+
+impl IndexMut<Player> for Reserves {
+    fn index_mut(&mut self, player: Player) -> &mut Reserve {
+        &mut self.0[player.index()]
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Reserve {
-    reg: u8,
-    cap: u8,
+/// Where a player's stones are, from `Board::positional_scores`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionalScores {
+    /// Per square, 0 for a corner and 1 more for every half step towards the
+    /// center
+    pub center: i32,
+    /// Per square, 0 for the middle ring and 1 more for every step towards
+    /// the nearest edge
+    pub edges: i32,
 }
 
-#[derive(Debug)]
+/// A turn that has been played, with what it did: the wall it crushed, which
+/// is also needed to undo it, and how the game stood after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveRecord {
+    pub turn: Turn,
+    pub crushed: Option<Loc>,
+    pub result: GameResult,
+}
+
+/// Everything `GameState::unmake` needs to take back a turn played by
+/// `GameState::make`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use = "the turn can only be taken back with this"]
+pub struct Unmake {
+    turn: Turn,
+    crushed: Option<Loc>,
+    result: GameResult,
+    clock: Option<Clock>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct GameState {
     current_player: Player,
     board: Board,
-    reserves: HashMap<Player, Reserve>,
+    reserves: Reserves,
+    result: GameResult,
+    /// Flats awarded to Black when counting, in half flats so that fractional
+    /// komi (which rules out draws) can be represented
+    komi: i32,
+    /// Turns played since the start of the game, which may be more than
+    /// `history` holds if the game was loaded from a position
+    ply: usize,
+    /// Who had the first turn, so who the opening swap applies to first
+    first_player: Player,
+    history: Vec<MoveRecord>,
+    roads: Roads,
+    win_rules: WinRules,
+    clock: Option<Clock>,
 }
 
+/// The board as a grid labelled like algebraic squares, with stacks listed
+/// bottom to top, under who is to move and what they have left
 impl fmt::Display for GameState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
+        writeln!(
             f,
-            "current_player: {:?},\nreserves: {:?},\nboard:\n{},",
-            self.current_player, self.reserves, self.board
-        )
-    }
-}
+            "to move: {}, ply: {}, result: {:?}",
+            self.current_player, self.ply, self.result
+        )?;
+        let reserve = |player| self.reserves[player];
+        let (white, black) = (reserve(Player::White), reserve(Player::Black));
+        writeln!(
+            f,
+            "reserves: 1: {}+{}C, 2: {}+{}C",
+            white.reg, white.cap, black.reg, black.cap
+        )?;
 
-impl GameState {
-    pub fn new(size: usize) -> GameState {
-        let reserve = match size {
-            3 => Reserve { reg: 10, cap: 0 },
-            4 => Reserve { reg: 15, cap: 0 },
-            5 => Reserve { reg: 21, cap: 1 },
-            6 => Reserve { reg: 30, cap: 1 },
-            7 => Reserve { reg: 40, cap: 2 },
-            8 => Reserve { reg: 50, cap: 2 },
-            _ => panic!("Board size should be between 3 and 8 for a valid game"),
+        let size = self.board.size();
+        let squares: Vec<Vec<String>> = self
+            .board
+            .stacks
+            .chunks(size)
+            .map(|row| {
+                row.iter()
+                    .map(|stack| {
+                        if stack.is_empty() {
+                            ".".to_owned()
+                        } else {
+                            stack.iter().map(|stone| stone.to_string()).collect()
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let width = squares.iter().flatten().map(String::len).max().unwrap_or(1);
+        let line = |label: String, cells: Vec<String>| {
+            let cells: Vec<String> = cells.iter().map(|cell| format!("{cell:width$}")).collect();
+            format!("{label} {}", cells.join(" ")).trim_end().to_owned()
         };
-        GameState {
-            current_player: Player::White,
-            board: Board::new(size),
-            reserves: HashMap::from([(Player::White, reserve), (Player::Black, reserve)]),
+        for (row, squares) in squares.into_iter().enumerate() {
+            writeln!(f, "{}", line(format!("{} |", size - row), squares))?;
         }
+        let files = (0..size)
+            .map(|col| char::from(b'a' + col as u8).to_string())
+            .collect();
+        write!(f, "{}", line("   ".to_owned(), files))
     }
+}
 
-    pub fn valid_turn(&self, turn: &Turn) -> bool {
+/// Same as `Display`, so that failed assertions show a readable board
+impl fmt::Debug for GameState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl GameState {
+    /// Panics if `size` isn't between 3 and 8, see `try_new`
+    pub fn new(size: usize) -> GameState {
+        Self::with_komi(size, 0)
+    }
+
+    /// A new game, or an error if `size` isn't between 3 and 8
+    pub fn try_new(size: usize) -> Result<GameState, SizeError> {
+        Self::try_with_win_rules(size, 0, WinRules::default())
+    }
+
+    /// `komi` is given in half flats
+    pub fn with_komi(size: usize, komi: i32) -> GameState {
+        Self::with_win_rules(size, komi, WinRules::default())
+    }
+
+    pub fn with_win_rules(size: usize, komi: i32, win_rules: WinRules) -> GameState {
+        Self::try_with_win_rules(size, komi, win_rules)
+            .expect("Board size should be between 3 and 8 for a valid game")
+    }
+
+    pub fn try_with_win_rules(
+        size: usize,
+        komi: i32,
+        win_rules: WinRules,
+    ) -> Result<GameState, SizeError> {
+        let reserve = Reserve::starting(size).ok_or(SizeError(size))?;
+        let board = Board::new(size);
+        Ok(GameState {
+            current_player: Player::White,
+            roads: Roads::build(&board),
+            board,
+            reserves: Reserves::new(reserve),
+            result: GameResult::Ongoing,
+            komi,
+            ply: 0,
+            first_player: Player::White,
+            history: Vec::new(),
+            win_rules,
+            clock: None,
+        })
+    }
+
+    /// The same game with `first_player` to move, for casual games where
+    /// Black starts. The opening swap applies to them first. Meant for a game
+    /// nothing has been played in yet.
+    pub fn with_first_player(mut self, first_player: Player) -> GameState {
+        debug_assert!(
+            self.ply == 0 && self.history.is_empty(),
+            "turns were already played"
+        );
+        self.current_player = first_player;
+        self.first_player = first_player;
+        self
+    }
+
+    /// Whether the game is at its first turn on an empty board
+    fn is_new_game(&self) -> bool {
+        self.ply == 0 && self.board.all_stones().next().is_none()
+    }
+
+    /// Who moved first, or White for a position that was loaded partway
+    /// through a game without a way to tell
+    pub fn first_player(&self) -> Player {
+        self.first_player
+    }
+
+    /// The ply as PTN and TPS count it, where a game Black started skipped
+    /// White's first turn
+    fn notation_ply(&self) -> usize {
+        self.ply + usize::from(self.first_player == Player::Black)
+    }
+
+    /// The same position played with `komi` and `win_rules`, e.g. for one
+    /// loaded from TPS, which has neither. Meant for a game nothing has been
+    /// played in yet, as its result is worked out again.
+    pub fn with_rules(mut self, komi: i32, win_rules: WinRules) -> GameState {
+        debug_assert!(self.history.is_empty(), "turns were already played");
+        self.komi = komi;
+        self.win_rules = win_rules;
+        self.result = self.compute_result(self.current_player.next());
+        self.end_if_stuck();
+        self
+    }
+
+    /// Replays `moves` from the start of a game, returning `None` if any of them
+    /// is illegal
+    pub fn from_moves(size: usize, komi: i32, moves: &[Turn]) -> Option<GameState> {
+        Self::with_komi(size, komi).replay(moves)
+    }
+
+    /// Plays `moves` in order, returning `None` if any of them is illegal
+    fn replay(mut self, moves: &[Turn]) -> Option<GameState> {
+        for turn in moves {
+            if !(self.apply_turn(turn)) {
+                return None;
+            }
+        }
+        Some(self)
+    }
+
+    pub fn result(&self) -> GameResult {
+        self.result
+    }
+
+    pub fn current_player(&self) -> Player {
+        self.current_player
+    }
+
+    /// The most stones a spread can pick up, which is the board size
+    pub fn carry_limit(&self) -> usize {
+        self.board.size()
+    }
+
+    /// The pieces `player` has left to place
+    pub fn reserve(&self, player: Player) -> Reserve {
+        self.reserves[player]
+    }
+
+    /// 1 if the player to move would fill the last empty square if both
+    /// players only placed from now on, -1 if their opponent would, and 0 on
+    /// a full board. A cheap guess at who has the tempo as the board fills up
+    /// for a flat win.
+    pub fn empty_square_parity(&self) -> i32 {
+        match self.board.empty_squares().len() {
+            0 => 0,
+            empty if empty % 2 == 1 => 1,
+            _ => -1,
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn komi(&self) -> i32 {
+        self.komi
+    }
+
+    pub fn win_rules(&self) -> WinRules {
+        self.win_rules
+    }
+
+    pub fn clock(&self) -> Option<Clock> {
+        self.clock
+    }
+
+    /// Times the game with `clock`, or stops timing it if `None`
+    pub fn set_clock(&mut self, clock: Option<Clock>) {
+        self.clock = clock;
+    }
+
+    /// Runs the clock of the player to move for `elapsed`, and if they run out
+    /// of time the game ends with their opponent winning on time. Does nothing
+    /// if the game isn't timed or is already over.
+    pub fn tick(&mut self, elapsed: Duration) {
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+        if self.result.is_over() {
+            return;
+        }
+        if clock.tick(self.current_player, elapsed) {
+            self.result = GameResult::TimeoutWin(self.current_player.next());
+        }
+    }
+
+    /// The player to move gives up, and their opponent wins
+    pub fn resign(&mut self) -> Result<(), TurnError> {
+        if self.result.is_over() {
+            return Err(TurnError::GameOver);
+        }
+        self.result = GameResult::ResignationWin(self.current_player.next());
+        Ok(())
+    }
+
+    /// The squares whose stacks changed since this was last called, see
+    /// `Board::take_dirty`
+    pub fn take_dirty(&mut self) -> Vec<Loc> {
+        self.board.take_dirty()
+    }
+
+    /// How many turns have been played, including any before a TPS position
+    /// was loaded
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    /// The number of the current move in PTN, where a move is a turn by each
+    /// player. White's is first even when they skipped it for Black.
+    pub fn move_number(&self) -> u32 {
+        (self.notation_ply() / 2 + 1) as u32
+    }
+
+    pub fn history(&self) -> &[MoveRecord] {
+        &self.history
+    }
+
+    /// Where the last turn flattened a wall with a capstone, if it did
+    pub fn last_crushed(&self) -> Option<Loc> {
+        self.history.last().and_then(|info| info.crushed)
+    }
+
+    pub fn valid_turn(&self, turn: &Turn) -> bool {
+        self.check_turn(turn).is_ok()
+    }
+
+    /// Like `valid_turn`, but says which rule an illegal turn breaks
+    pub fn check_turn(&self, turn: &Turn) -> Result<(), TurnError> {
+        if self.result.is_over() {
+            return Err(TurnError::GameOver);
+        }
         if !(turn.player() == self.current_player) {
-            return false;
+            return Err(TurnError::WrongPlayer);
+        }
+        if !(self.opening_allows(turn)) {
+            return Err(TurnError::OpeningNotFlat);
+        }
+        self.reserves_allow(turn)?;
+        if !(self.board.valid_turn(turn)) {
+            return Err(TurnError::IllegalOnBoard);
         }
+        if !(self.win_rules_allow(turn)) {
+            return Err(TurnError::CompletesOpponentsRoad);
+        }
+        Ok(())
+    }
+
+    /// Every rule an illegal turn breaks, in the order `check_turn` checks
+    /// them, for debugging and teaching. Empty if the turn is legal. Slower
+    /// than `check_turn`, which stops at the first. `CompletesOpponentsRoad`
+    /// can only be checked for a turn the board allows, so it's only reported
+    /// when nothing else is wrong.
+    pub fn turn_errors(&self, turn: &Turn) -> Vec<TurnError> {
+        let mut errors = Vec::new();
+        if self.result.is_over() {
+            errors.push(TurnError::GameOver);
+        }
+        if !(turn.player() == self.current_player) {
+            errors.push(TurnError::WrongPlayer);
+        }
+        if !(self.opening_allows(turn)) {
+            errors.push(TurnError::OpeningNotFlat);
+        }
+        if let Err(error) = self.reserves_allow(turn) {
+            errors.push(error);
+        }
+        if !(self.board.valid_turn(turn)) {
+            errors.push(TurnError::IllegalOnBoard);
+        }
+        if errors.is_empty() && !(self.win_rules_allow(turn)) {
+            errors.push(TurnError::CompletesOpponentsRoad);
+        }
+        errors
+    }
+
+    /// Whether the player has a stone left of the kind `turn` places, or
+    /// during the opening swap their opponent does
+    fn reserves_allow(&self, turn: &Turn) -> Result<(), TurnError> {
+        let swapped = self.opening_swap(turn);
+        let turn = swapped.as_ref().unwrap_or(turn);
         if let Turn::Place {
             loc: _,
-            player: _,
+            player,
             typ,
         } = turn
         {
             match typ {
                 StoneType::Flat | StoneType::Standing => {
-                    if self.reserves[&turn.player()].reg == 0 {
-                        return false;
+                    if self.reserves[*player].reg == 0 {
+                        return Err(TurnError::NoStones);
                     }
                 }
                 StoneType::Capstone => {
-                    if self.reserves[&turn.player()].cap == 0 {
-                        return false;
+                    if self.reserves[*player].cap == 0 {
+                        return Err(TurnError::NoCapstones);
                     }
                 }
             }
         }
-        self.board.valid_turn(turn)
+        Ok(())
     }
 
-    pub fn apply_turn(&mut self, turn: &Turn) -> bool {
-        if !(self.valid_turn(turn)) {
-            return false;
+    /// Whether this is either player's first turn
+    fn is_opening(&self) -> bool {
+        self.ply < 2
+    }
+
+    /// The placement `turn` makes on the board instead, if it's either
+    /// player's first turn, where they place one of their opponent's stones.
+    /// `None` for every other turn, which is played as it is.
+    fn opening_swap(&self, turn: &Turn) -> Option<Turn> {
+        match turn {
+            Turn::Place { loc, player, typ } if self.is_opening() => Some(Turn::Place {
+                loc: *loc,
+                player: player.next(),
+                typ: *typ,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether `turn`, which must be legal on the board, is allowed by
+    /// `WinRules::no_suicide`
+    fn win_rules_allow(&self, turn: &Turn) -> bool {
+        let spread = matches!(
+            turn,
+            Turn::Move {
+                loc: _,
+                player: _,
+                dir: _,
+                total: _,
+                drops: _,
+            }
+        );
+        if !(self.win_rules.no_suicide && spread) {
+            return true;
         }
+        let player = turn.player();
+        !self.completes_road(turn, player.next()) || self.completes_road(turn, player)
+    }
+
+    /// Each player's first turn has to place a flat. This is the only place
+    /// the rule is checked, everything that plays turns goes through here.
+    fn opening_allows(&self, turn: &Turn) -> bool {
+        if !(self.is_opening()) {
+            return true;
+        }
+        matches!(
+            turn,
+            Turn::Place {
+                loc: _,
+                player: _,
+                typ: StoneType::Flat,
+            }
+        )
+    }
+
+    pub fn apply_turn(&mut self, turn: &Turn) -> bool {
+        self.try_apply_turn(turn).is_ok()
+    }
+
+    /// Plays `turn`, or says why it can't be played without changing
+    /// anything. Tak has no passing, so a turn that doesn't place or move a
+    /// stone is never legal, and nothing else hands the move to the other
+    /// player. In a timed game the player gets their increment afterwards.
+    pub fn try_apply_turn(&mut self, turn: &Turn) -> Result<(), TurnError> {
+        let Unmake {
+            turn,
+            crushed,
+            result: _,
+            clock: _,
+        } = self.make(turn)?;
+        self.history.push(MoveRecord {
+            turn,
+            crushed,
+            result: self.result,
+        });
+        Ok(())
+    }
+
+    /// Plays `turn` like `try_apply_turn`, but without adding it to `history`.
+    /// Passing the token it returns to `unmake` puts everything back as it
+    /// was, so trying out a turn doesn't need a copy of the game.
+    pub fn make(&mut self, turn: &Turn) -> Result<Unmake, TurnError> {
+        self.check_turn(turn)?;
 
-        self.board.apply_turn(turn);
+        let result = self.result;
+        let clock = self.clock;
+        let swapped = self.opening_swap(turn);
+        let played = swapped.as_ref().unwrap_or(turn);
+        let before = Roads::before(&self.board, &played.squares());
+        let crushed = self.board.apply_move_unchecked(played);
+        self.roads.update(&self.board, &before);
+        if let Some(clock) = &mut self.clock {
+            clock.add_increment(self.current_player);
+        }
         self.current_player = self.current_player.next();
+        self.ply += 1;
         if let Turn::Place {
             loc: _,
-            player: _,
+            player,
             typ,
-        } = turn
+        } = played
         {
-            self.reserves
-                .entry(turn.player())
-                .and_modify(|res| match typ {
-                    StoneType::Flat | StoneType::Standing => res.reg -= 1,
-                    StoneType::Capstone => res.cap -= 1,
-                });
+            let reserve = &mut self.reserves[*player];
+            match typ {
+                StoneType::Flat | StoneType::Standing => reserve.reg -= 1,
+                StoneType::Capstone => reserve.cap -= 1,
+            }
+        }
+        self.result = self.compute_result(turn.player());
+        self.end_if_stuck();
+        self.assert_conservation();
+
+        Ok(Unmake {
+            turn: turn.clone(),
+            crushed,
+            result,
+            clock,
+        })
+    }
+
+    /// Takes back the turn `make` returned `unmake` for, which must be the
+    /// last turn played
+    pub fn unmake(&mut self, unmake: Unmake) {
+        let Unmake {
+            turn,
+            crushed,
+            result,
+            clock,
+        } = unmake;
+        self.current_player = turn.player();
+        self.ply -= 1;
+        let swapped = self.opening_swap(&turn);
+        let played = swapped.as_ref().unwrap_or(&turn);
+        let before = Roads::before(&self.board, &played.squares());
+        self.board.undo_move_unchecked(played, crushed);
+        self.roads.update(&self.board, &before);
+        if let Turn::Place {
+            loc: _,
+            player,
+            typ,
+        } = played
+        {
+            let reserve = &mut self.reserves[*player];
+            match typ {
+                StoneType::Flat | StoneType::Standing => reserve.reg += 1,
+                StoneType::Capstone => reserve.cap += 1,
+            }
         }
+        self.result = result;
+        self.clock = clock;
+    }
+
+    /// Like `try_apply_turn`, but first runs the player's clock for the `used`
+    /// time they spent on the turn. If that runs them out of time, they lose
+    /// on time instead and the turn fails with `TurnError::GameOver`.
+    pub fn try_apply_timed_turn(&mut self, turn: &Turn, used: Duration) -> Result<(), TurnError> {
+        self.check_turn(turn)?;
+        self.tick(used);
+        self.try_apply_turn(turn)
+    }
+
+    /// Panics if any player's pieces on the board and in reserve don't add up
+    /// to what they started with. Only checked with debug assertions on.
+    pub fn assert_conservation(&self) {
+        if !(cfg!(debug_assertions)) {
+            return;
+        }
+        let starting = Reserve::starting(self.board.size())
+            .expect("Board size should be between 3 and 8 for a valid game");
+        for player in Player::all() {
+            let (reg, cap) = self.board.pieces_on_board(player);
+            let reserve = self.reserves[player];
+            assert_eq!(
+                reg + usize::from(reserve.reg),
+                usize::from(starting.reg),
+                "player {player}'s stones aren't conserved"
+            );
+            assert_eq!(
+                cap + usize::from(reserve.cap),
+                usize::from(starting.cap),
+                "player {player}'s capstones aren't conserved"
+            );
+        }
+    }
+
+    /// Tak has no passing, so if the player to move has no legal turn the game
+    /// is decided by counting flats rather than being left stuck. The standard
+    /// rules always leave a placement available, but this keeps the engine from
+    /// hanging if that ever stops being true.
+    fn end_if_stuck(&mut self) {
+        if !self.result.is_over() && !self.has_legal_turn(self.current_player) {
+            self.result = self.flat_result(self.komi);
+        }
+    }
+
+    /// Whether `player` has any turn at all, checking the cheap case of an
+    /// available placement first
+    fn has_legal_turn(&self, player: Player) -> bool {
+        let reserve = self.reserves[player];
+        if (reserve.reg > 0 || reserve.cap > 0) && !self.board.is_full() {
+            return true;
+        }
+        let size = self.board.size();
+        (0..size * size).any(|i| {
+            spread_turns(
+                &self.board,
+                Loc::from_index(i, size),
+                player,
+                self.carry_limit(),
+            )
+            .iter()
+            .any(|turn| self.win_rules_allow(turn))
+        })
+    }
 
+    /// Takes back the last turn, returning `false` if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(MoveRecord {
+            turn,
+            crushed,
+            result: _,
+        }) = self.history.pop()
+        else {
+            return false;
+        };
+        // Turns can't be played after the game ends, so it was ongoing before.
+        // Time spent isn't given back.
+        self.unmake(Unmake {
+            turn,
+            crushed,
+            result: GameResult::Ongoing,
+            clock: self.clock,
+        });
         true
     }
+
+    pub fn to_saved(&self) -> SavedGame {
+        let mut start = self.clone();
+        while start.undo() {}
+        SavedGame {
+            size: self.board.size(),
+            komi: self.komi,
+            moves: self.history.iter().map(|info| info.turn.clone()).collect(),
+            result: self.result,
+            win_rules: self.win_rules,
+            first_player: self.first_player,
+            start: (!start.is_new_game()).then(|| start.to_tps()),
+        }
+    }
+
+    /// Everything that differs between `self` and `other`, e.g. to check that a
+    /// turn only changed what it should have
+    pub fn diff(&self, other: &GameState) -> GameDiff {
+        let size = self.board.size().max(other.board.size());
+        let stack = |board: &Board, loc| {
+            if board.valid_loc(loc) {
+                board[loc].clone()
+            } else {
+                Vec::new()
+            }
+        };
+        let squares = (0..size * size)
+            .map(|i| Loc::from_index(i, size))
+            .filter_map(|loc| {
+                let before = stack(&self.board, loc);
+                let after = stack(&other.board, loc);
+                (before != after).then_some((loc, StackDiff { before, after }))
+            })
+            .collect();
+        let current_player = (self.current_player != other.current_player)
+            .then_some((self.current_player, other.current_player));
+        let reserves = Player::all()
+            .into_iter()
+            .filter_map(|player| {
+                let before = self.reserves[player];
+                let after = other.reserves[player];
+                (before != after).then_some(ReserveDiff {
+                    player,
+                    stones: i32::from(after.reg) - i32::from(before.reg),
+                    capstones: i32::from(after.cap) - i32::from(before.cap),
+                })
+            })
+            .collect();
+        GameDiff {
+            squares,
+            current_player,
+            reserves,
+        }
+    }
+
+    /// Every legal turn for the player to move
+    pub fn legal_turns(&self) -> Vec<Turn> {
+        if self.result.is_over() {
+            return Vec::new();
+        }
+        self.turns_for(self.current_player)
+    }
+
+    /// Plays up to `plies` random legal turns, e.g. to vary the start of games
+    /// against the bot. Turns that would end the game are never picked, so it
+    /// stops early if the only turns left would.
+    pub fn random_opening<R: Rng>(&mut self, plies: usize, rng: &mut R) {
+        for _ in 0..plies {
+            let mut turns = self.legal_turns();
+            turns.shuffle(rng);
+            let ongoing = turns.into_iter().find_map(|turn| {
+                let mut next = self.clone();
+                (next.apply_turn(&turn) && !next.result.is_over()).then_some(next)
+            });
+            let Some(next) = ongoing else {
+                return;
+            };
+            *self = next;
+        }
+    }
+
+    /// Every turn `player` could make if it were their turn
+    fn turns_for(&self, player: Player) -> Vec<Turn> {
+        let size = self.board.size();
+        let mut turns = self.placements_for(player);
+        for row in 0..size {
+            for col in 0..size {
+                let loc = Loc { row, col };
+                if self.board[loc]
+                    .last()
+                    .is_some_and(|top| top.owner == player)
+                {
+                    turns.extend(spread_turns(&self.board, loc, player, self.carry_limit()));
+                }
+            }
+        }
+        turns.retain(|turn| self.opening_allows(turn) && self.win_rules_allow(turn));
+        turns
+    }
+
+    /// How many turns `legal_turns` would return, mostly without making them.
+    /// Spreads are counted from how far each stack can reach, unless the
+    /// no-suicide rule means checking each one.
+    pub fn legal_move_count(&self) -> usize {
+        if self.result.is_over() {
+            return 0;
+        }
+        let player = self.current_player;
+        let size = self.board.size();
+        let empty = self.board.empty_squares().len();
+        let reserve = self.reserves[player];
+        if self.is_opening() {
+            let swapped = self.reserves[player.next()];
+            return if swapped.reg > 0 { empty } else { 0 };
+        }
+        let mut count = 0;
+        if reserve.reg > 0 {
+            count += 2 * empty;
+        }
+        if reserve.cap > 0 {
+            count += empty;
+        }
+        for row in 0..size {
+            for col in 0..size {
+                let loc = Loc { row, col };
+                count += if self.win_rules.no_suicide {
+                    spread_turns(&self.board, loc, player, self.carry_limit())
+                        .iter()
+                        .filter(|turn| self.win_rules_allow(turn))
+                        .count()
+                } else {
+                    spread_count(&self.board, loc, player, self.carry_limit())
+                };
+            }
+        }
+        count
+    }
+
+    /// Every legal placement for the player to move, without any spreads
+    pub fn legal_placements(&self) -> Vec<Turn> {
+        if self.result.is_over() {
+            return Vec::new();
+        }
+        let mut turns = self.placements_for(self.current_player);
+        turns.retain(|turn| self.opening_allows(turn));
+        turns
+    }
+
+    /// Every placement `player` can afford on an empty square, ignoring the
+    /// opening rule
+    fn placements_for(&self, player: Player) -> Vec<Turn> {
+        let reserve = self.reserves[player];
+        let mut types = Vec::new();
+        if reserve.reg > 0 {
+            types.extend([StoneType::Flat, StoneType::Standing]);
+        }
+        if reserve.cap > 0 {
+            types.push(StoneType::Capstone);
+        }
+        self.board
+            .empty_squares()
+            .into_iter()
+            .flat_map(|loc| {
+                types.iter().map(move |typ| Turn::Place {
+                    loc,
+                    player,
+                    typ: *typ,
+                })
+            })
+            .collect()
+    }
+
+    /// Every turn that would leave `player` with more flats on top of stacks
+    /// than they have now, regardless of whose turn it currently is. Spreads
+    /// count for what they uncover as well as what they cover.
+    pub fn flat_changing_moves(&self, player: Player) -> Vec<Turn> {
+        let flats = self.board.flat_count(player);
+        self.turns_for(player)
+            .into_iter()
+            .filter(|turn| {
+                let mut board = self.board.clone();
+                board.apply_move_unchecked(self.opening_swap(turn).as_ref().unwrap_or(turn));
+                board.flat_count(player) > flats
+            })
+            .collect()
+    }
+
+    /// Every turn that would immediately complete a road for `player`,
+    /// regardless of whose turn it currently is
+    pub fn road_threat_moves(&self, player: Player) -> Vec<Turn> {
+        self.turns_for(player)
+            .into_iter()
+            .filter(|turn| self.completes_road(turn, player))
+            .collect()
+    }
+
+    /// The `road_threat_moves` `player` would have after `turn`, for marking
+    /// the moves that make a threat. Empty if `turn` is illegal.
+    pub fn threats_after(&self, turn: &Turn, player: Player) -> Vec<Turn> {
+        let mut next = self.clone();
+        if !(next.apply_turn(turn)) {
+            return Vec::new();
+        }
+        next.road_threat_moves(player)
+    }
+
+    /// Whether playing `turn` would lose, either straight away or to any reply
+    /// the opponent could make. Illegal turns don't count.
+    pub fn gives_opponent_win(&self, turn: &Turn) -> bool {
+        let opponent = self.current_player.next();
+        let mut next = self.clone();
+        if !(next.apply_turn(turn)) {
+            return false;
+        }
+        if next.result.is_over() {
+            return next.result.winner() == Some(opponent);
+        }
+        next.legal_turns().iter().any(|reply| {
+            let Ok(unmake) = next.make(reply) else {
+                return false;
+            };
+            let lost = next.result.winner() == Some(opponent);
+            next.unmake(unmake);
+            lost
+        })
+    }
+
+    /// Whether `player` has a road, without searching the board for one
+    pub fn check_road(&self, player: Player) -> bool {
+        self.roads.has_road(player)
+    }
+
+    /// White's and then Black's `Board::shortest_road_distance`
+    pub fn road_distances(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.board.shortest_road_distance(Player::White),
+            self.board.shortest_road_distance(Player::Black),
+        )
+    }
+
+    /// Whether `player` could complete a road with a single turn
+    pub fn is_tak(&self, player: Player) -> bool {
+        self.turns_for(player)
+            .iter()
+            .any(|turn| self.completes_road(turn, player))
+    }
+
+    fn completes_road(&self, turn: &Turn, player: Player) -> bool {
+        let mut board = self.board.clone();
+        board.apply_move_unchecked(self.opening_swap(turn).as_ref().unwrap_or(turn));
+        board.check_road(player)
+    }
+
+    /// Whether `turn`, a spread, would leave `opponent` without a road they
+    /// have on the board now, by covering one of its squares with the
+    /// mover's stone. Placements can't, they only go on empty squares, and
+    /// neither can turns the board doesn't allow.
+    pub fn spread_breaks_road(&self, turn: &Turn, opponent: Player) -> bool {
+        let spread = matches!(
+            turn,
+            Turn::Move {
+                loc: _,
+                player: _,
+                dir: _,
+                total: _,
+                drops: _,
+            }
+        );
+        if !(spread && self.board.valid_turn(turn) && self.board.check_road(opponent)) {
+            return false;
+        }
+        let mut board = self.board.clone();
+        board.apply_move_unchecked(turn);
+        !board.check_road(opponent)
+    }
+
+    /// How many spreads `player` could make with stacks topped by their
+    /// capstones, regardless of whose turn it is. Zero means every capstone
+    /// they have played is trapped.
+    pub fn capstone_mobility(&self, player: Player) -> usize {
+        let size = self.board.size();
+        (0..size * size)
+            .map(|index| Loc::from_index(index, size))
+            .filter(|loc| {
+                self.board[*loc].last().is_some_and(|top| {
+                    top.owner == player && matches!(top.typ, StoneType::Capstone)
+                })
+            })
+            .map(|loc| spread_turns(&self.board, loc, player, self.carry_limit()).len())
+            .sum()
+    }
+
+    /// How many more flats `player` needs to win on flats if the board fills
+    /// up, taking komi into account. The remaining empty squares are assumed to
+    /// be filled with flats alternately, starting with the player to move. Zero
+    /// means the player is already on track to win.
+    pub fn flats_to_flat_win(&self, player: Player) -> i32 {
+        let empty = self.board.empty_count() as i32;
+        let (to_move_share, other_share) = ((empty + 1) / 2, empty / 2);
+        let (own_share, opponent_share) = if player == self.current_player {
+            (to_move_share, other_share)
+        } else {
+            (other_share, to_move_share)
+        };
+        let own = self.board.flat_count(player) as i32 + own_share;
+        let opponent = self.board.flat_count(player.next()) as i32 + opponent_share;
+        // In half flats, positive when `player` is ahead
+        let komi = match player {
+            Player::White => -self.komi,
+            Player::Black => self.komi,
+        };
+        let margin = 2 * (own - opponent) + komi;
+        if margin > 0 { 0 } else { -margin / 2 + 1 }
+    }
+
+    /// Determines whether the game ended with `mover`'s turn, in order of
+    /// precedence:
+    /// 1. `mover` has a road, even if the turn also gave their opponent one
+    /// 2. Their opponent has a road
+    /// 3. The board is full or a player is out of pieces, so flats are counted
+    ///
+    /// So a turn that fills the board while completing a road is a road win.
+    fn compute_result(&self, mover: Player) -> GameResult {
+        if self.check_road(mover) {
+            return GameResult::RoadWin(mover);
+        }
+        if self.check_road(mover.next()) {
+            return GameResult::RoadWin(mover.next());
+        }
+        let out_of_pieces = self
+            .reserves
+            .values()
+            .any(|res| res.reg == 0 && res.cap == 0);
+        if self.board.is_full() || out_of_pieces {
+            return self.flat_result(self.komi);
+        }
+        GameResult::Ongoing
+    }
+
+    /// The outcome of counting flats with `komi` (in half flats), which is
+    /// always a draw if only roads win
+    fn flat_result(&self, komi: i32) -> GameResult {
+        if self.win_rules.roads_only {
+            return GameResult::Draw;
+        }
+        let white = 2 * self.board.flat_count(Player::White) as i32;
+        let black = 2 * self.board.flat_count(Player::Black) as i32 + komi;
+        match white.cmp(&black) {
+            std::cmp::Ordering::Greater => GameResult::FlatWin(Player::White),
+            std::cmp::Ordering::Less => GameResult::FlatWin(Player::Black),
+            std::cmp::Ordering::Equal => GameResult::Draw,
+        }
+    }
+
+    /// What the result would have been with a different komi (in half flats),
+    /// leaving the game's own komi alone. Only games that ended by counting
+    /// flats are affected, roads and timeouts win regardless of komi and
+    /// ongoing games stay ongoing.
+    pub fn result_with_komi(&self, komi_half_flats: i32) -> GameResult {
+        match self.result {
+            GameResult::FlatWin(_) | GameResult::Draw => self.flat_result(komi_half_flats),
+            GameResult::Ongoing
+            | GameResult::RoadWin(_)
+            | GameResult::TimeoutWin(_)
+            | GameResult::ResignationWin(_) => self.result,
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The stacks on a square in two games, listed bottom to top
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackDiff {
+    pub before: Vec<Stone>,
+    pub after: Vec<Stone>,
+}
 
-    #[test]
-    fn it_works() {
+/// How many pieces a player's reserve gained between two games, negative if
+/// they were spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveDiff {
+    pub player: Player,
+    pub stones: i32,
+    pub capstones: i32,
+}
+
+/// The result of `GameState::diff`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameDiff {
+    /// Squares whose stacks differ
+    pub squares: Vec<(Loc, StackDiff)>,
+    /// The player to move in each game, if they differ
+    pub current_player: Option<(Player, Player)>,
+    pub reserves: Vec<ReserveDiff>,
+}
+
+/// Everything needed to rebuild a game, in a form suitable for saving to disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedGame {
+    pub size: usize,
+    pub komi: i32,
+    pub moves: Vec<Turn>,
+    pub result: GameResult,
+    /// Missing from games saved before there was a choice
+    #[serde(default)]
+    pub win_rules: WinRules,
+    /// Missing from games saved before Black could move first
+    #[serde(default)]
+    pub first_player: Player,
+    /// The position as TPS the moves were played from, e.g. for a puzzle, or
+    /// `None` for a game from an empty board
+    #[serde(default)]
+    pub start: Option<String>,
+}
+
+impl SavedGame {
+    /// Replays the saved moves, returning `None` if the board size is invalid,
+    /// any move is illegal, or the replayed result doesn't match the saved one.
+    /// Clocks and resignations aren't saved as moves, so a game lost on time
+    /// or by resigning only has to still be ongoing after its moves.
+    pub fn restore(&self) -> Option<GameState> {
+        let start = match &self.start {
+            Some(tps) => GameState::from_tps(tps)
+                .ok()
+                .filter(|state| state.board.size() == self.size)?
+                .with_rules(self.komi, self.win_rules),
+            None => GameState::try_with_win_rules(self.size, self.komi, self.win_rules)
+                .ok()?
+                .with_first_player(self.first_player),
+        };
+        let mut state = start.replay(&self.moves)?;
+        if let GameResult::TimeoutWin(_) | GameResult::ResignationWin(_) = self.result
+            && state.result == GameResult::Ongoing
+        {
+            state.result = self.result;
+        }
+        (state.result == self.result).then_some(state)
+    }
+}
+
+/// Checks a whole game of PTN `moves`, returning how it stands after the last
+/// one, or the first move that couldn't be read or played. `size` has to be
+/// playable, as for `GameState::with_komi`.
+pub fn validate_game(size: usize, komi: i32, moves: &[&str]) -> Result<GameResult, ReplayError> {
+    GameState::with_komi(size, komi)
+        .replay_ptn(moves)
+        .map(|state| state.result())
+}
+
+/// Every legal spread of the stack at `loc` by `player`, picking up at most
+/// `carry_limit` stones. Only partitions that stay on the board are generated,
+/// no longer than the distance to the edge in their direction, so
+/// `Board::valid_turn` only has the rest of the rules (walls, capstones) to
+/// reject.
+fn spread_turns(board: &Board, loc: Loc, player: Player, carry_limit: usize) -> Vec<Turn> {
+    let mut turns = Vec::new();
+    let max_total = board.max_pickup(loc, player, carry_limit);
+    if max_total == 0 {
+        return turns;
+    }
+    for dir in Dir::ALL {
+        let max_len = board.distance_to_edge(loc, dir);
+        for total in 1..=max_total {
+            for drops in partitions(total, max_len) {
+                let turn = Turn::Move {
+                    loc,
+                    player,
+                    dir,
+                    total,
+                    drops,
+                };
+                if board.valid_turn(&turn) {
+                    turns.push(turn);
+                }
+            }
+        }
+    }
+    turns
+}
+
+/// How many turns `spread_turns` would return. Drops can go as far as the
+/// first wall or capstone in each direction, or onto a wall with the
+/// capstone alone.
+fn spread_count(board: &Board, loc: Loc, player: Player, carry_limit: usize) -> usize {
+    let max_total = board.max_pickup(loc, player, carry_limit);
+    if max_total == 0 {
+        return 0;
+    }
+    let capstone = board[loc].last().unwrap().typ == StoneType::Capstone;
+    let mut count = 0;
+    for dir in Dir::ALL {
+        let max_len = board.distance_to_edge(loc, dir);
+        let mut reach = 0;
+        let mut crushes = false;
+        let mut next_loc = loc;
+        while reach < max_len {
+            next_loc = next_loc.move_in(dir);
+            match board[next_loc].last().map(|top| top.typ) {
+                None | Some(StoneType::Flat) => reach += 1,
+                Some(StoneType::Standing) => {
+                    crushes = capstone;
+                    break;
+                }
+                Some(StoneType::Capstone) => break,
+            }
+        }
+        for total in 1..=max_total {
+            count += (1..=reach)
+                .map(|len| compositions(total, len))
+                .sum::<usize>();
+            // The rest across every square it can reach, then the capstone
+            // alone onto the wall
+            if crushes {
+                count += compositions(total - 1, reach);
+            }
+        }
+    }
+    count
+}
+
+/// How many ways there are of splitting `total` into exactly `parts`
+/// positive parts, which is `total - 1` choose `parts - 1`
+fn compositions(total: usize, parts: usize) -> usize {
+    if parts == 0 || parts > total {
+        return usize::from(total == 0 && parts == 0);
+    }
+    let (n, k) = (total - 1, parts - 1);
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+/// All ordered ways of splitting `total` into at most `max_len` positive parts
+fn partitions(total: usize, max_len: usize) -> Vec<Vec<usize>> {
+    if total == 0 {
+        return vec![Vec::new()];
+    }
+    if max_len == 0 {
+        return Vec::new();
+    }
+    (1..=total)
+        .flat_map(|first| {
+            partitions(total - first, max_len - 1)
+                .into_iter()
+                .map(move |mut rest| {
+                    rest.insert(0, first);
+                    rest
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notation::{ApplyPtnError, ParseError};
+
+    #[test]
+    fn it_works() {
         let mut state = GameState::new(5);
         println!("{}", state);
+        // Each places the other's flat
         assert!(state.apply_turn(&Turn::Place {
-            loc: Loc { row: 0, col: 0 },
+            loc: Loc { row: 1, col: 0 },
             player: Player::White,
             typ: StoneType::Flat,
         }));
         println!("{}", state);
         assert!(state.apply_turn(&Turn::Place {
-            loc: Loc { row: 1, col: 0 },
+            loc: Loc { row: 0, col: 0 },
             player: Player::Black,
             typ: StoneType::Flat,
         }));
@@ -456,4 +2385,1599 @@ mod tests {
         }));
         println!("{}", state);
     }
+
+    fn place(row: usize, col: usize, player: Player, typ: StoneType) -> Turn {
+        Turn::Place {
+            loc: Loc { row, col },
+            player,
+            typ,
+        }
+    }
+
+    /// Plays `count` games of random legal turns, going through the board
+    /// sizes in order, and the same games every time for a given `seed`.
+    /// `each` is called with the position before every turn, the turn and the
+    /// position after it. Returns the finished games.
+    pub(super) fn random_games(
+        seed: u64,
+        count: usize,
+        mut each: impl FnMut(&GameState, &Turn, &GameState),
+    ) -> Vec<GameState> {
+        use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+
+        let mut rng = SmallRng::seed_from_u64(seed);
+        (0..count)
+            .map(|game| {
+                let mut state = GameState::new(3 + game % 6);
+                while !state.result().is_over() {
+                    let before = state.clone();
+                    let turn = before.legal_turns().choose(&mut rng).unwrap().clone();
+                    assert!(state.apply_turn(&turn));
+                    each(&before, &turn, &state);
+                }
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn size_3_rejects_capstones() {
+        let mut state = GameState::new(3);
+        assert!(!state.apply_turn(&place(0, 0, Player::White, StoneType::Capstone)));
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(!state.apply_turn(&place(1, 1, Player::Black, StoneType::Capstone)));
+        assert_eq!(state.result(), GameResult::Ongoing);
+    }
+
+    #[test]
+    fn size_3_road_with_flats_only() {
+        let mut state = GameState::new(3);
+        assert!(state.apply_turn(&place(0, 2, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(0, 0, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(1, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(1, 2, Player::Black, StoneType::Flat)));
+        assert_eq!(state.result(), GameResult::Ongoing);
+        assert!(state.apply_turn(&place(2, 0, Player::White, StoneType::Flat)));
+        assert_eq!(state.result(), GameResult::RoadWin(Player::White));
+        // No more turns once the game is over
+        assert!(!state.apply_turn(&place(2, 2, Player::Black, StoneType::Flat)));
+    }
+
+    #[test]
+    fn first_turns_place_the_opponents_stone() {
+        let mut state = GameState::new(5);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(2, 2, Player::White, StoneType::Flat)));
+        let owner =
+            |state: &GameState, row, col| state.board[Loc { row, col }].last().unwrap().owner;
+        assert_eq!(owner(&state, 0, 0), Player::Black);
+        assert_eq!(owner(&state, 4, 4), Player::White);
+        assert_eq!(owner(&state, 2, 2), Player::White);
+        assert_eq!(state.reserves[Player::White].reg, 19);
+        assert_eq!(state.reserves[Player::Black].reg, 20);
+        assert!(state.undo());
+        assert!(state.undo());
+        assert_eq!(owner(&state, 0, 0), Player::Black);
+        assert!(state.board[Loc { row: 4, col: 4 }].is_empty());
+        assert_eq!(state.reserves[Player::White].reg, 21);
+        assert_eq!(state.reserves[Player::Black].reg, 20);
+    }
+
+    #[test]
+    fn off_board_moves_are_rejected() {
+        let mut state = GameState::new(3);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(2, 2, Player::Black, StoneType::Flat)));
+        assert!(!state.apply_turn(&Turn::Move {
+            loc: Loc { row: 0, col: 0 },
+            player: Player::White,
+            dir: Dir::North,
+            total: 1,
+            drops: vec![1],
+        }));
+        assert!(!state.apply_turn(&Turn::Move {
+            loc: Loc { row: 3, col: 0 },
+            player: Player::White,
+            dir: Dir::South,
+            total: 1,
+            drops: vec![1],
+        }));
+    }
+
+    #[test]
+    fn finds_both_road_threats() {
+        let mut state = GameState::new(4);
+        // The first two are swapped
+        for (row, col) in [
+            (0, 0),
+            (1, 0),
+            (1, 1),
+            (2, 1),
+            (1, 2),
+            (0, 2),
+            (3, 1),
+            (2, 2),
+            (3, 2),
+            (2, 0),
+            (3, 3),
+        ] {
+            let player = state.current_player;
+            assert!(state.apply_turn(&place(row, col, player, StoneType::Flat)));
+        }
+        assert_eq!(state.result(), GameResult::Ongoing);
+        let threats = state.road_threat_moves(Player::White);
+        assert_eq!(threats.len(), 2);
+        assert!(threats.contains(&place(1, 3, Player::White, StoneType::Flat)));
+        assert!(threats.contains(&place(3, 0, Player::White, StoneType::Flat)));
+        assert!(state.is_tak(Player::White));
+    }
+
+    #[test]
+    fn placing_next_to_a_line_makes_a_threat() {
+        let state = GameState::from_tps("x5/x5/1,1,1,x2/x5/2,2,2,x2 1 4").unwrap();
+        assert!(state.road_threat_moves(Player::White).is_empty());
+        let setup = place(2, 3, Player::White, StoneType::Flat);
+        // One square, with a flat or the capstone
+        assert_eq!(
+            state.threats_after(&setup, Player::White),
+            vec![
+                place(2, 4, Player::White, StoneType::Flat),
+                place(2, 4, Player::White, StoneType::Capstone),
+            ]
+        );
+        // A wall makes no road, so no threat
+        let wall = place(2, 3, Player::White, StoneType::Standing);
+        assert!(state.threats_after(&wall, Player::White).is_empty());
+        let taken = place(4, 0, Player::White, StoneType::Flat);
+        assert!(state.threats_after(&taken, Player::White).is_empty());
+    }
+
+    #[test]
+    fn spreading_onto_a_road_breaks_it() {
+        let state = GameState::from_tps("x5/x5/2,2,2,2,2/x2,21,x2/1,x4 1 4").unwrap();
+        let ptn = |ptn| Turn::from_ptn(ptn, Player::White, 5).unwrap();
+        assert!(state.spread_breaks_road(&ptn("c2+"), Player::Black));
+        // Nowhere near the road, or not a spread
+        assert!(!state.spread_breaks_road(&ptn("a1>"), Player::Black));
+        assert!(!state.spread_breaks_road(&ptn("e1"), Player::Black));
+        // White has no road to break
+        assert!(!state.spread_breaks_road(&ptn("c2+"), Player::White));
+        // Spreading onto your own road keeps it
+        let under = GameState::from_tps("x5/x5/2,2,2,2,2/x2,12,x2/1,x4 2 4").unwrap();
+        let black = Turn::from_ptn("c2+", Player::Black, 5).unwrap();
+        assert!(!under.spread_breaks_road(&black, Player::Black));
+    }
+
+    #[test]
+    fn undo_restores_previous_state() {
+        let mut state = GameState::new(5);
+        let before = state.clone();
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(1, 1, Player::White, StoneType::Capstone)));
+        assert!(state.apply_turn(&place(1, 0, Player::Black, StoneType::Standing)));
+        let before_crush = state.clone();
+        assert!(state.apply_turn(&Turn::Move {
+            loc: Loc { row: 1, col: 1 },
+            player: Player::White,
+            dir: Dir::West,
+            total: 1,
+            drops: vec![1],
+        }));
+        assert_eq!(
+            state.history().last().unwrap().crushed,
+            Some(Loc { row: 1, col: 0 })
+        );
+        assert!(state.undo());
+        assert_eq!(state, before_crush);
+        while state.undo() {}
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn first_turns_must_place_flats() {
+        let mut state = GameState::new(5);
+        for typ in [StoneType::Standing, StoneType::Capstone] {
+            assert!(!state.apply_turn(&place(0, 0, Player::White, typ)));
+        }
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(!state.apply_turn(&place(4, 4, Player::Black, StoneType::Standing)));
+        assert!(state.legal_turns().iter().all(|turn| matches!(
+            turn,
+            Turn::Place {
+                loc: _,
+                player: _,
+                typ: StoneType::Flat
+            }
+        )));
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(1, 1, Player::White, StoneType::Capstone)));
+    }
+
+    #[test]
+    fn placements_respect_reserves_and_opening() {
+        let mut state = GameState::new(5);
+        assert_eq!(state.legal_placements().len(), 25);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert_eq!(state.legal_placements().len(), 24);
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        // Flats, walls and the capstone on each of the 23 empty squares
+        assert_eq!(state.legal_placements().len(), 3 * 23);
+        assert!(state.apply_turn(&place(1, 1, Player::White, StoneType::Capstone)));
+        assert!(state.apply_turn(&place(3, 3, Player::Black, StoneType::Flat)));
+        assert_eq!(state.legal_placements().len(), 2 * 21);
+        assert!(
+            state
+                .legal_placements()
+                .iter()
+                .all(|turn| state.valid_turn(turn))
+        );
+    }
+
+    #[test]
+    fn would_crush_only_for_capstone_onto_wall() {
+        let state = GameState::from_tps("x5/x5/x,1C,2S,x2/x,21,1,x2/x5 1 6").unwrap();
+        let crush = Turn::from_ptn("b3>", Player::White, 5).unwrap();
+        assert_eq!(
+            state.board().would_crush(&crush),
+            Some(Loc { row: 2, col: 2 })
+        );
+        let spread = Turn::from_ptn("2b2>11", Player::White, 5).unwrap();
+        assert!(state.valid_turn(&spread));
+        assert_eq!(state.board().would_crush(&spread), None);
+        let placement = Turn::from_ptn("a1", Player::White, 5).unwrap();
+        assert_eq!(state.board().would_crush(&placement), None);
+    }
+
+    #[test]
+    fn unchecked_moves_match_checked_ones() {
+        let state = GameState::from_tps("x5/x5/x,1C,2S,x2/x,21,1,x2/x5 1 6").unwrap();
+        for turn in state.legal_turns() {
+            let mut checked = state.clone();
+            assert!(checked.apply_turn(&turn));
+            let mut board = state.board().clone();
+            let crushed = board.apply_move_unchecked(&turn);
+            assert!(board == *checked.board(), "{turn:?}");
+            assert_eq!(crushed, checked.last_crushed());
+            board.undo_move_unchecked(&turn, crushed);
+            assert!(board == *state.board(), "{turn:?}");
+        }
+    }
+
+    #[test]
+    fn random_openings_are_playable() {
+        use rand::{SeedableRng, rngs::SmallRng};
+
+        let mut openings = HashSet::new();
+        for seed in 0..20 {
+            let mut state = GameState::new(5);
+            state.random_opening(4, &mut SmallRng::seed_from_u64(seed));
+            assert_eq!(state.ply(), 4);
+            assert_eq!(state.result(), GameResult::Ongoing);
+            assert!(!state.legal_turns().is_empty());
+            let moves: Vec<Turn> = state
+                .history()
+                .iter()
+                .map(|info| info.turn.clone())
+                .collect();
+            assert!(GameState::from_moves(5, 0, &moves).is_some_and(|replayed| replayed == state));
+            openings.insert(state.to_tps());
+        }
+        assert!(openings.len() > 1);
+
+        // Never wins on the way, even when there's a road to be had
+        let mut state = GameState::from_tps("x5/x5/x5/x5/1,1,1,1,x 1 5").unwrap();
+        for seed in 0..20 {
+            let mut opened = state.clone();
+            opened.random_opening(1, &mut SmallRng::seed_from_u64(seed));
+            assert_eq!(opened.result(), GameResult::Ongoing);
+        }
+        state.random_opening(0, &mut SmallRng::seed_from_u64(0));
+        assert_eq!(state.ply(), 8);
+    }
+
+    #[test]
+    fn leaving_a_road_open_gives_the_win_away() {
+        let state = GameState::from_tps("x5/x5/x5/x5/2,2,2,2,x 1 5").unwrap();
+        let ptn = |turn: &str| Turn::from_ptn(turn, Player::White, 5).unwrap();
+        assert!(state.gives_opponent_win(&ptn("a5")));
+        assert!(state.gives_opponent_win(&ptn("Ce5")));
+        assert!(!state.gives_opponent_win(&ptn("e1")));
+        assert!(!state.gives_opponent_win(&ptn("Se1")));
+        // Not a legal turn
+        assert!(!state.gives_opponent_win(&ptn("a1")));
+
+        // Uncovering one of their stones can complete a road for them at once
+        let state = GameState::from_tps("x5/x5/x5/x5/2,2,2,2,21 1 5").unwrap();
+        assert!(state.gives_opponent_win(&ptn("e1+")));
+        assert!(!state.gives_opponent_win(&ptn("e1<")));
+    }
+
+    #[test]
+    fn move_number_counts_pairs_of_turns() {
+        let mut state = GameState::new(5);
+        assert_eq!(state.move_number(), 1);
+        for ptn in ["a1", "e5", "c3"] {
+            assert!(state.apply_turn(&Turn::from_ptn(ptn, state.current_player(), 5).unwrap()));
+        }
+        assert_eq!(state.move_number(), 2);
+        assert_eq!(state.current_player(), Player::Black);
+        assert!(state.to_tps().ends_with(" 2 2"));
+    }
+
+    #[test]
+    fn capstone_targets_include_crushes() {
+        let state = GameState::from_tps("x5/x5/x,121C,x,2S,x/x5/x5 1 6").unwrap();
+        let board = state.board();
+        let cap = Loc { row: 2, col: 1 };
+        let targets = board.capstone_targets(cap, Player::White, 5);
+        let ptn = |turn: &str| Turn::from_ptn(turn, Player::White, 5).unwrap();
+        for crush in ["2b3>11", "3b3>21"] {
+            assert!(targets.contains(&ptn(crush)), "{crush}");
+            assert_eq!(board.would_crush(&ptn(crush)), Some(Loc { row: 2, col: 3 }));
+        }
+        // Only the capstone alone can flatten the wall
+        assert!(!targets.contains(&ptn("3b3>12")));
+        assert!(targets.contains(&ptn("b3>")));
+        assert!(targets.iter().all(|turn| state.valid_turn(turn)));
+        let legal: Vec<Turn> = state
+            .legal_turns()
+            .into_iter()
+            .filter(|turn| turn.squares()[0] == cap && turn.squares().len() > 1)
+            .collect();
+        assert_eq!(targets, legal);
+
+        assert!(board.capstone_targets(cap, Player::Black, 5).is_empty());
+        assert!(
+            board
+                .capstone_targets(Loc { row: 2, col: 3 }, Player::Black, 5)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn empty_turns_cannot_pass() {
+        let mut state = GameState::from_ptn_moves(5, 0, &["a1", "e5", "c3"]).unwrap();
+        let before = state.clone();
+        let stay = |drops: Vec<usize>| Turn::Move {
+            loc: Loc { row: 2, col: 2 },
+            player: Player::Black,
+            dir: Dir::North,
+            total: drops.iter().sum(),
+            drops,
+        };
+        for turn in [stay(vec![]), stay(vec![0]), stay(vec![1, 0])] {
+            assert_eq!(state.try_apply_turn(&turn), Err(TurnError::IllegalOnBoard));
+            assert_eq!(state.current_player(), Player::Black);
+            assert_eq!(state, before);
+        }
+        // Picking up nothing from an empty square isn't a way to pass either
+        let nothing = Turn::Move {
+            loc: Loc { row: 0, col: 0 },
+            player: Player::Black,
+            dir: Dir::East,
+            total: 0,
+            drops: vec![],
+        };
+        assert_eq!(
+            state.try_apply_turn(&nothing),
+            Err(TurnError::IllegalOnBoard)
+        );
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn validate_finished_game() {
+        let moves = ["e5", "a1", "b1", "e4", "c1", "e3", "d1", "e2", "e1"];
+        assert_eq!(
+            validate_game(5, 0, &moves),
+            Ok(GameResult::RoadWin(Player::White))
+        );
+        assert_eq!(validate_game(5, 0, &moves[..4]), Ok(GameResult::Ongoing));
+    }
+
+    #[test]
+    fn validate_stops_at_first_bad_move() {
+        assert_eq!(
+            validate_game(5, 0, &["a1", "e5", "a1", "e4", "zz"]),
+            Err(ReplayError {
+                ply: 2,
+                ptn: "a1".to_owned(),
+                cause: ApplyPtnError::Illegal(TurnError::IllegalOnBoard),
+            })
+        );
+        assert_eq!(
+            validate_game(5, 0, &["a1", "e5", "zz"]),
+            Err(ReplayError {
+                ply: 2,
+                ptn: "zz".to_owned(),
+                cause: ApplyPtnError::Parse(ParseError::UnexpectedChar { pos: 1, ch: 'z' }),
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Board size should be between 3 and 8")]
+    fn validate_needs_a_playable_size() {
+        let _ = validate_game(2, 0, &["a1"]);
+    }
+
+    #[test]
+    fn flat_changing_moves_gain_flats() {
+        // White has a lone flat on a1 and two flats on a Black one on b2
+        let state = GameState::from_tps("x5/x5/x5/x,211,x3/1,x4 1 4").unwrap();
+        let gaining = state.flat_changing_moves(Player::White);
+        let ptn = |ptn| Turn::from_ptn(ptn, Player::White, 5).unwrap();
+        assert!(gaining.contains(&ptn("c3")));
+        assert!(!gaining.contains(&ptn("Sc3")));
+        assert!(!gaining.contains(&ptn("Cc3")));
+        // Leaving the other of White's flats on top of b2
+        assert!(gaining.contains(&ptn("b2>")));
+        // Uncovering Black's flat to move both of White's together, or just
+        // moving a flat
+        assert!(!gaining.contains(&ptn("2b2>")));
+        assert!(gaining.contains(&ptn("2b2>11")));
+        assert!(!gaining.contains(&ptn("a1+")));
+        assert!(gaining.iter().all(|turn| turn.player() == Player::White));
+        // The first flat White places is Black's
+        let opening = GameState::new(5);
+        assert!(opening.flat_changing_moves(Player::White).is_empty());
+    }
+
+    #[test]
+    fn top_chars_of_a_position() {
+        let state = GameState::from_tps("1,2S,x3/x,12,1C,x2/x5/2C,x3,1S/x4,21 1 8").unwrap();
+        let board = state.board();
+        let grid: Vec<String> = (0..5)
+            .map(|row| (0..5).map(|col| board.top_char(Loc { row, col })).collect())
+            .collect();
+        assert_eq!(grid, ["fS...", ".Fc..", ".....", "C...s", "....f"]);
+    }
+
+    #[test]
+    fn has_capstone_finds_played_capstones() {
+        // Still in reserve
+        let mut state = GameState::from_ptn_moves(5, 0, &["a1", "e5"]).unwrap();
+        assert!(!state.board().has_capstone(Player::White));
+        assert!(state.apply_turn(&place(2, 2, Player::White, StoneType::Capstone)));
+        assert!(state.board().has_capstone(Player::White));
+        assert!(!state.board().has_capstone(Player::Black));
+
+        // Rules can't bury a capstone, but the board doesn't assume that
+        let mut board = Board::new(5);
+        let stone = |owner, typ| Stone { owner, typ };
+        board.set_stack(
+            Loc { row: 0, col: 0 },
+            vec![
+                stone(Player::Black, StoneType::Capstone),
+                stone(Player::White, StoneType::Flat),
+            ],
+        );
+        assert!(board.has_capstone(Player::Black));
+        assert!(!board.has_capstone(Player::White));
+    }
+
+    #[test]
+    fn unplayed_sizes_are_errors() {
+        assert_eq!(GameState::try_new(2).unwrap_err(), SizeError(2));
+        assert_eq!(GameState::try_new(9).unwrap_err(), SizeError(9));
+        assert_eq!(
+            SizeError(9).to_string(),
+            "board size 9 is not between 3 and 8"
+        );
+        for size in 3..=8 {
+            assert!(GameState::try_new(size).unwrap() == GameState::new(size));
+        }
+    }
+
+    #[test]
+    fn empty_squares_shrink_as_stones_are_placed() {
+        let mut state = GameState::new(3);
+        assert_eq!(state.board().empty_squares().len(), 9);
+        let mut placed = Vec::new();
+        for (row, col) in [(0, 0), (2, 2), (1, 1), (0, 2)] {
+            let loc = Loc { row, col };
+            let player = state.current_player();
+            assert!(state.apply_turn(&place(row, col, player, StoneType::Flat)));
+            placed.push(loc);
+            let empty = state.board().empty_squares();
+            assert_eq!(empty.len(), 9 - placed.len());
+            assert!(placed.iter().all(|loc| !empty.contains(loc)));
+            assert!(empty.iter().all(|loc| state.board()[*loc].is_empty()));
+        }
+        // Moving a stone empties the square it left
+        assert!(state.apply_turn(&Turn::Move {
+            loc: Loc { row: 1, col: 1 },
+            player: Player::White,
+            dir: Dir::East,
+            total: 1,
+            drops: vec![1],
+        }));
+        assert!(
+            state
+                .board()
+                .empty_squares()
+                .contains(&Loc { row: 1, col: 1 })
+        );
+        assert_eq!(state.board().empty_squares().len(), 5);
+    }
+
+    #[test]
+    fn center_and_edge_control() {
+        let scores = |tps, player| {
+            let state = GameState::from_tps(tps).unwrap();
+            state.board().positional_scores(player)
+        };
+        let central = scores("x5/x5/x,1,1,x2/x2,1,x2/x5 2 3", Player::White);
+        let corners = scores("1,x4/x5/x5/x5/1,x3,1 2 3", Player::White);
+        assert!(central.center > corners.center, "{central:?} {corners:?}");
+        assert!(central.edges < corners.edges, "{central:?} {corners:?}");
+        // The corners are as far from the center as it gets
+        assert_eq!(
+            corners,
+            PositionalScores {
+                center: 0,
+                edges: 6
+            }
+        );
+        assert_eq!(
+            scores("1,x4/x5/x5/x5/1,x3,1 2 3", Player::Black),
+            PositionalScores {
+                center: 0,
+                edges: 0
+            }
+        );
+    }
+
+    #[test]
+    fn all_stones_covers_every_stack() {
+        let state = GameState::from_tps("x5/x5/x,21C,2S,1S,x/x,2C,x3/x5 1 6").unwrap();
+        let board = state.board();
+        let stones: Vec<_> = board.all_stones().collect();
+        let heights: usize = (0..25).map(|i| board[Loc::from_index(i, 5)].len()).sum();
+        assert_eq!(stones.len(), heights);
+        for (loc, layer, stone) in &stones {
+            assert_eq!(board[*loc][*layer], *stone);
+        }
+        // The buried flat under the capstone at b3
+        let b3 = Loc::from_algebraic("b3", 5).unwrap();
+        assert!(stones.contains(&(
+            b3,
+            0,
+            Stone {
+                owner: Player::Black,
+                typ: StoneType::Flat,
+            }
+        )));
+    }
+
+    #[test]
+    fn rejected_turns_leave_walls_and_capstones_alone() {
+        let mut state = GameState::from_tps("x5/x5/x,21C,2S,1S,x/x,2C,x3/x5 1 6").unwrap();
+        let before = state.clone();
+        let tops = |state: &GameState| {
+            [(2, 1), (2, 2), (2, 3), (3, 1)]
+                .map(|(row, col)| state.board()[Loc { row, col }].last().unwrap().typ)
+        };
+        assert_eq!(
+            tops(&state),
+            [
+                StoneType::Capstone,
+                StoneType::Standing,
+                StoneType::Standing,
+                StoneType::Capstone
+            ]
+        );
+        // Onto a wall, a capstone carrying more than itself onto a wall, a wall
+        // onto a wall and a capstone onto a capstone
+        for ptn in ["c3", "Sc3", "2b3>", "2b3>11", "d3<", "b3-"] {
+            let turn = Turn::from_ptn(ptn, Player::White, 5).unwrap();
+            assert_eq!(
+                state.try_apply_turn(&turn),
+                Err(TurnError::IllegalOnBoard),
+                "{ptn}"
+            );
+            assert!(state == before, "{ptn}");
+        }
+
+        // The capstone on its own can crush, and undoing puts the wall back
+        assert!(state.apply_turn(&Turn::from_ptn("b3>", Player::White, 5).unwrap()));
+        assert_eq!(
+            state.board()[Loc { row: 2, col: 2 }][0].typ,
+            StoneType::Flat
+        );
+        assert!(state.undo());
+        assert_eq!(tops(&state), tops(&before));
+    }
+
+    #[test]
+    fn walls_only_flatten_when_crushed() {
+        let walls = |board: &Board| {
+            board
+                .stacks
+                .iter()
+                .flatten()
+                .filter(|stone| stone.typ == StoneType::Standing)
+                .count()
+        };
+        let mut crushes = 0;
+        random_games(3, 40, |before, turn, after| {
+            let placed = matches!(
+                turn,
+                Turn::Place {
+                    loc: _,
+                    player: _,
+                    typ: StoneType::Standing,
+                }
+            );
+            let crushed = after.last_crushed().is_some();
+            crushes += usize::from(crushed);
+            assert_eq!(
+                walls(after.board()) + usize::from(crushed),
+                walls(before.board()) + usize::from(placed),
+                "{}",
+                turn.to_ptn(after.board().size())
+            );
+        });
+        assert!(crushes > 0);
+    }
+
+    #[test]
+    fn last_crushed_reports_only_the_crushing_turn() {
+        let mut state = GameState::new(5);
+        assert_eq!(state.last_crushed(), None);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(2, 2, Player::White, StoneType::Capstone)));
+        assert_eq!(state.last_crushed(), None);
+        assert!(state.apply_turn(&place(2, 3, Player::Black, StoneType::Standing)));
+        assert!(state.apply_turn(&Turn::Move {
+            loc: Loc { row: 2, col: 2 },
+            player: Player::White,
+            dir: Dir::East,
+            total: 1,
+            drops: vec![1],
+        }));
+        assert_eq!(state.last_crushed(), Some(Loc { row: 2, col: 3 }));
+        assert!(state.apply_turn(&place(4, 3, Player::Black, StoneType::Flat)));
+        assert_eq!(state.last_crushed(), None);
+        assert!(state.undo());
+        assert_eq!(state.last_crushed(), Some(Loc { row: 2, col: 3 }));
+    }
+
+    #[test]
+    fn diff_after_one_turn() {
+        let mut state = GameState::new(5);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(1, 1, Player::White, StoneType::Flat)));
+        let before = state.clone();
+        assert!(state.apply_turn(&place(2, 2, Player::Black, StoneType::Capstone)));
+        let stone = Stone {
+            owner: Player::Black,
+            typ: StoneType::Capstone,
+        };
+        assert_eq!(
+            before.diff(&state),
+            GameDiff {
+                squares: vec![(
+                    Loc { row: 2, col: 2 },
+                    StackDiff {
+                        before: Vec::new(),
+                        after: vec![stone],
+                    }
+                )],
+                current_player: Some((Player::Black, Player::White)),
+                reserves: vec![ReserveDiff {
+                    player: Player::Black,
+                    stones: 0,
+                    capstones: -1,
+                }],
+            }
+        );
+        assert_eq!(
+            state.diff(&state),
+            GameDiff {
+                squares: Vec::new(),
+                current_player: None,
+                reserves: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn conservation_holds_through_spreads() {
+        let mut state = GameState::new(5);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(4, 4, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(1, 1, Player::White, StoneType::Standing)));
+        assert!(state.apply_turn(&place(0, 1, Player::Black, StoneType::Capstone)));
+        assert!(state.apply_turn(&place(3, 3, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&Turn::Move {
+            loc: Loc { row: 0, col: 1 },
+            player: Player::Black,
+            dir: Dir::West,
+            total: 1,
+            drops: vec![1],
+        }));
+        state.assert_conservation();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "player 2's stones aren't conserved")]
+    fn conservation_catches_duplicated_stones() {
+        let mut state = GameState::new(5);
+        assert!(state.apply_turn(&place(0, 0, Player::White, StoneType::Flat)));
+        state.board.place_stone(
+            Loc { row: 0, col: 0 },
+            Stone {
+                owner: Player::Black,
+                typ: StoneType::Flat,
+            },
+        );
+        state.assert_conservation();
+    }
+
+    #[test]
+    fn counts_top_stones_by_type() {
+        let state = GameState::from_tps("1,2S,1C,x2/21,12,1S,x2/2C,x4/x5/x4,221 1 10").unwrap();
+        assert_eq!(state.board().top_type_counts(Player::White), (3, 1, 1));
+        assert_eq!(state.board().top_type_counts(Player::Black), (1, 1, 1));
+        assert_eq!(
+            GameState::new(5).board().top_type_counts(Player::White),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn stack_composition() {
+        let state = GameState::from_tps("x3,12,2S/x,1,x3/x5/x2,1C,x2/x5 2 4").unwrap();
+        let board = state.board();
+        let loc = Loc { row: 0, col: 3 };
+        assert_eq!(board.stack_owners(loc), vec![Player::White, Player::Black]);
+        assert_eq!(
+            board.stack_types(loc),
+            vec![StoneType::Flat, StoneType::Flat]
+        );
+        let loc = Loc { row: 0, col: 4 };
+        assert_eq!(board.stack_owners(loc), vec![Player::Black]);
+        assert_eq!(board.stack_types(loc), vec![StoneType::Standing]);
+        assert!(board.stack_owners(Loc { row: 4, col: 4 }).is_empty());
+    }
+
+    #[test]
+    fn max_pickup_limits() {
+        let state = GameState::from_tps("2121212,x3/x,21,x2/x4/x3,1 1 12").unwrap();
+        let board = state.board();
+        let tall = Loc { row: 0, col: 0 };
+        assert_eq!(board.max_pickup(tall, Player::Black, 4), 4);
+        assert_eq!(board.max_pickup(tall, Player::Black, 8), 7);
+        assert_eq!(board.max_pickup(tall, Player::White, 4), 0);
+        assert_eq!(
+            board.max_pickup(Loc { row: 1, col: 1 }, Player::White, 4),
+            2
+        );
+        assert_eq!(
+            board.max_pickup(Loc { row: 3, col: 3 }, Player::Black, 4),
+            0
+        );
+        assert_eq!(
+            board.max_pickup(Loc { row: 2, col: 2 }, Player::White, 4),
+            0
+        );
+    }
+
+    #[test]
+    fn counts_buried_stones() {
+        let state = GameState::from_tps("1212121C,x4/x,21,x3/2,x4/x5/x4,1 2 12").unwrap();
+        assert_eq!(state.board().buried_counts(Player::White), 3);
+        assert_eq!(state.board().buried_counts(Player::Black), 4);
+    }
+
+    #[test]
+    fn counts_stones_buried_under_opponent_walls_and_capstones() {
+        let state = GameState::from_tps("x5/x5/x,12C,x3/x,21S,x3/x,121,x3 1 5").unwrap();
+        assert_eq!(state.board().buried_under_opponent(Player::White), 1);
+        // Black's flat at b1 is only under a White flat
+        assert_eq!(state.board().buried_under_opponent(Player::Black), 1);
+        assert_eq!(state.board().buried_counts(Player::Black), 2);
+    }
+
+    #[test]
+    fn influence_spreads_to_neighbours() {
+        let state = GameState::from_tps("x4/x,1,x,2/x4/x3,221 2 5").unwrap();
+        let map = state.board().influence_map();
+        let at = |row, col| map[Loc { row, col }.to_index(4)];
+        assert_eq!(at(1, 1), 1);
+        assert_eq!(at(0, 1), 1);
+        assert_eq!(at(0, 3), -1);
+        // Between the two flats
+        assert_eq!(at(1, 2), 0);
+        // The white stack of 3 outweighs the black flat
+        assert_eq!(at(2, 3), 2);
+        assert_eq!(at(3, 3), 3);
+        assert_eq!(at(0, 0), 0);
+        assert_eq!(map.iter().filter(|&&v| v != 0).count(), 9);
+    }
+
+    #[test]
+    fn road_relevant_squares_cover_every_edge_group() {
+        let state = GameState::from_tps("x,1,x3/x,1,x,1,x/x,1,x3/x3,1,1/1S,x,2,x2 2 5").unwrap();
+        let relevant = state.board().road_relevant_squares(Player::White);
+        let expected: HashSet<Loc> = [(0, 1), (1, 1), (2, 1), (3, 3), (3, 4)]
+            .into_iter()
+            .map(|(row, col)| Loc { row, col })
+            .collect();
+        // Leaves out the flat cut off in the middle and the wall on the edge
+        assert_eq!(relevant, expected);
+        assert_eq!(
+            state.board().road_relevant_squares(Player::Black),
+            HashSet::from([Loc { row: 4, col: 2 }])
+        );
+    }
+
+    #[test]
+    fn road_distances_count_missing_stones() {
+        // White has most of a column, Black's stones are too far apart to help
+        // each other much
+        let state = GameState::from_tps("x,1,x3/x,1,2,x2/x,1,x,2,x/2,x4/x5 2 6").unwrap();
+        assert_eq!(state.road_distances(), (Some(2), Some(4)));
+        let state = GameState::from_tps("x,1,x3/x,1,2,x2/x,1,x,2,x/2,1,x3/x,1,x3 2 6").unwrap();
+        assert_eq!(state.road_distances().0, Some(0));
+        assert_eq!(GameState::new(5).road_distances(), (Some(5), Some(5)));
+        // Black's road cuts every one of White's off
+        let state = GameState::from_tps("x,2,x/2,2,2/x,2,x 1 4").unwrap();
+        assert_eq!(state.road_distances(), (None, Some(0)));
+    }
+
+    #[test]
+    fn describe_explains_the_ending() {
+        let road = GameState::from_ptn_moves(
+            5,
+            0,
+            &["e5", "a1", "a2", "e4", "a3", "e3", "a4", "e2", "a5"],
+        )
+        .unwrap();
+        assert_eq!(road.result(), GameResult::RoadWin(Player::White));
+        let name = |player| match player {
+            Player::White => "White",
+            Player::Black => "Black",
+        };
+        assert_eq!(
+            road.result().describe(&road, name),
+            "White wins by a road from a1 to a5"
+        );
+        let path = road.board().winning_road(Player::White).unwrap();
+        assert_eq!(path.len(), 5);
+        assert!(road.board().winning_road(Player::Black).is_none());
+        let across = GameState::from_tps("x5/x5/x5/2,2,2,2,x/1,1,1,1,1 2 5").unwrap();
+        assert_eq!(
+            across.result().describe(&across, name),
+            "White wins by a road from a1 to e1"
+        );
+
+        // Black's last flat fills the board, 5 flats and komi to White's 4
+        let mut flats = GameState::from_tps("1,2,1/2,1,2/1,2,x 2 5").unwrap();
+        flats.komi = 4;
+        assert!(flats.apply_turn(&place(2, 2, Player::Black, StoneType::Flat)));
+        assert_eq!(flats.result(), GameResult::FlatWin(Player::Black));
+        assert_eq!(
+            flats.result().describe(&flats, name),
+            "Black wins on flats 7-4 (komi 2)"
+        );
+        assert_eq!(
+            GameResult::Ongoing.describe(&GameState::new(3), name),
+            "The game isn't over"
+        );
+    }
+
+    #[test]
+    fn road_axis() {
+        let state = GameState::from_tps("x,1,x3/x,1,2,x2/x,1,x,2,x/2,1,x3/x,1,x3 2 6").unwrap();
+        assert_eq!(state.board().road_axis(Player::White), Some(Axis::Vertical));
+        assert_eq!(state.board().road_axis(Player::Black), None);
+        let state = GameState::from_tps("x,1,x3/x,1,x3/1,1,1,1,1/x,1,x3/x,1,x3 2 6").unwrap();
+        assert_eq!(state.board().road_axis(Player::White), Some(Axis::Both));
+        let state = GameState::from_tps("x5/x5/1,1,1,1,1/x5/x5 2 6").unwrap();
+        assert_eq!(
+            state.board().road_axis(Player::White),
+            Some(Axis::Horizontal)
+        );
+    }
+
+    #[test]
+    fn display_shows_board_grid() {
+        let state = GameState::from_tps("x3,12,2S/x,1,x3/x5/x2,1C,x2/x5 2 4").unwrap();
+        assert_eq!(
+            state.to_string(),
+            "\
+to move: 2, ply: 7, result: Ongoing
+reserves: 1: 19+0C, 2: 19+1C
+5 | .  .  .  12 2S
+4 | .  1  .  .  .
+3 | .  .  .  .  .
+2 | .  .  1C .  .
+1 | .  .  .  .  .
+    a  b  c  d  e"
+        );
+    }
+
+    #[test]
+    fn indexing_by_row_and_column() {
+        let white = Stone {
+            owner: Player::White,
+            typ: StoneType::Flat,
+        };
+        for size in 3..=8 {
+            let mut board = Board::new(size);
+            assert_eq!(board.size(), size);
+            for index in 0..size * size {
+                let loc = Loc::from_index(index, size);
+                for _ in 0..=index {
+                    board.place_stone(loc, white);
+                }
+            }
+            for row in 0..size {
+                for col in 0..size {
+                    let loc = Loc { row, col };
+                    assert_eq!(board.stack_height(loc), row * size + col + 1);
+                }
+            }
+        }
+
+        let state = GameState::from_tps("x3,12,2S/x,1,x3/x5/x2,1C,x2/x5 2 4").unwrap();
+        let board = state.board();
+        assert_eq!(board[Loc { row: 0, col: 3 }].len(), 2);
+        assert_eq!(
+            board[Loc { row: 0, col: 4 }],
+            vec![Stone {
+                owner: Player::Black,
+                typ: StoneType::Standing,
+            }]
+        );
+        assert_eq!(board[Loc { row: 1, col: 1 }], vec![white]);
+        assert_eq!(board[Loc { row: 3, col: 2 }][0].typ, StoneType::Capstone);
+        assert!(board[Loc { row: 4, col: 0 }].is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "off the board")]
+    fn indexing_past_the_last_column_panics() {
+        // Would land on the next row's first square if it only checked the
+        // flat index
+        let board = Board::new(5);
+        let _ = &board[Loc { row: 1, col: 5 }];
+    }
+
+    #[test]
+    fn dirty_squares_are_the_ones_a_turn_touches() {
+        let mut state = GameState::from_tps("x5/x5/x,1C,2S,x2/x,21,1,x2/x5 1 6").unwrap();
+        assert_eq!(state.board.take_dirty().len(), 25);
+        assert!(state.board.take_dirty().is_empty());
+
+        let spread = Turn::from_ptn("2b2>11", Player::White, 5).unwrap();
+        assert!(state.apply_turn(&spread));
+        let touched = [(3, 1), (3, 2), (3, 3)].map(|(row, col)| Loc { row, col });
+        assert_eq!(state.board.take_dirty(), touched);
+        assert!(state.undo());
+        assert_eq!(state.board.take_dirty(), touched);
+
+        assert!(state.apply_turn(&Turn::from_ptn("a1", Player::White, 5).unwrap()));
+        assert_eq!(state.board.take_dirty(), [Loc { row: 4, col: 0 }]);
+    }
+
+    #[test]
+    fn roads_only_draws_a_full_board() {
+        const ROADS_ONLY: WinRules = WinRules {
+            roads_only: true,
+            no_suicide: false,
+        };
+        // Fills the board with White 5 flats to Black's 4
+        let full = |rules| {
+            let mut state = GameState::with_win_rules(3, 0, rules);
+            for ptn in ["c1", "a1", "b1", "a2", "c2", "b2", "a3", "c3", "b3"] {
+                assert!(state.apply_turn(&Turn::from_ptn(ptn, state.current_player(), 3).unwrap()));
+            }
+            state
+        };
+        assert_eq!(
+            full(WinRules::default()).result(),
+            GameResult::FlatWin(Player::White)
+        );
+        let roads_only = full(ROADS_ONLY);
+        assert_eq!(roads_only.result(), GameResult::Draw);
+        assert_eq!(roads_only.to_saved().restore(), Some(roads_only));
+        // Roads still win
+        let mut state = GameState::with_win_rules(3, 0, ROADS_ONLY);
+        for ptn in ["c3", "a1", "b1", "c2", "c1"] {
+            assert!(state.apply_turn(&Turn::from_ptn(ptn, state.current_player(), 3).unwrap()));
+        }
+        assert_eq!(state.result(), GameResult::RoadWin(Player::White));
+    }
+
+    #[test]
+    fn running_out_of_time_loses() {
+        let secs = Duration::from_secs;
+        let mut state = GameState::new(5);
+        state.set_clock(Some(Clock::new(secs(10), secs(2))));
+        let a1 = Turn::from_ptn("a1", Player::White, 5).unwrap();
+        assert_eq!(state.try_apply_timed_turn(&a1, secs(4)), Ok(()));
+        assert_eq!(state.clock().unwrap().remaining(Player::White), secs(8));
+
+        // Only the player to move's clock runs
+        state.tick(secs(9));
+        assert_eq!(state.result(), GameResult::Ongoing);
+        assert_eq!(state.clock().unwrap().remaining(Player::White), secs(8));
+        state.tick(secs(1));
+        assert_eq!(state.result(), GameResult::TimeoutWin(Player::White));
+        let e5 = Turn::from_ptn("e5", Player::Black, 5).unwrap();
+        assert_eq!(state.try_apply_turn(&e5), Err(TurnError::GameOver));
+
+        let saved = state.to_saved();
+        assert_eq!(saved.restore().unwrap().result(), state.result());
+        let mut ongoing = saved.clone();
+        ongoing.result = GameResult::TimeoutWin(Player::Black);
+        ongoing.moves.clear();
+        assert_eq!(ongoing.restore().unwrap().result(), ongoing.result);
+    }
+
+    #[test]
+    fn resigning_hands_the_win_over() {
+        let mut state = GameState::from_ptn_moves(5, 0, &["a1", "e5", "c3"]).unwrap();
+        assert_eq!(state.resign(), Ok(()));
+        assert_eq!(state.result(), GameResult::ResignationWin(Player::White));
+        assert_eq!(state.resign(), Err(TurnError::GameOver));
+        assert!(state.legal_turns().is_empty());
+        assert_eq!(state.result().to_ptn_result(), Some("1-0"));
+        let restored = state.to_saved().restore().unwrap();
+        assert_eq!(restored.result(), state.result());
+    }
+
+    #[test]
+    fn turn_errors_reports_every_broken_rule() {
+        let mut state = GameState::new(5);
+        for ptn in ["a1", "e5"] {
+            assert!(state.apply_turn(&Turn::from_ptn(ptn, state.current_player(), 5).unwrap()));
+        }
+        // Black, off the board, on White's turn
+        let turn = Turn::Place {
+            loc: Loc { row: 5, col: 0 },
+            player: Player::Black,
+            typ: StoneType::Flat,
+        };
+        assert_eq!(state.check_turn(&turn), Err(TurnError::WrongPlayer));
+        assert_eq!(
+            state.turn_errors(&turn),
+            vec![TurnError::WrongPlayer, TurnError::IllegalOnBoard]
+        );
+        let legal = Turn::from_ptn("b2", Player::White, 5).unwrap();
+        assert!(state.turn_errors(&legal).is_empty());
+    }
+
+    #[test]
+    fn black_can_move_first() {
+        let mut state = GameState::new(5).with_first_player(Player::Black);
+        assert_eq!(state.current_player(), Player::Black);
+        assert_eq!(state.first_player(), Player::Black);
+        let owner = |state: &GameState, name| {
+            let loc = Loc::from_algebraic(name, 5).unwrap();
+            state.board()[loc].last().unwrap().owner
+        };
+        let white_a1 = Turn::from_ptn("a1", Player::White, 5).unwrap();
+        assert_eq!(state.try_apply_turn(&white_a1), Err(TurnError::WrongPlayer));
+        // The swap applies to Black first, who places White's stone on ply 0
+        assert_eq!(state.ply(), 0);
+        assert_eq!(state.apply_ptn("a1"), Ok(GameResult::Ongoing));
+        assert_eq!(owner(&state, "a1"), Player::White);
+        assert_eq!(state.reserves[Player::White].reg, 20);
+        assert_eq!(state.reserves[Player::Black].reg, 21);
+
+        // Written down, White's first turn was skipped
+        assert_eq!(state.move_number(), 2);
+        assert_eq!(state.to_tps(), "x5/x5/x5/x5/1,x4 1 2");
+        assert!(state.to_ptn().ends_with("\n1. -- a1\n"));
+        assert!(!(state.to_ptn().contains("TPS")));
+        for copy in [
+            GameState::from_tps(&state.to_tps()).unwrap(),
+            GameState::from_share_code(&state.to_share_code()).unwrap(),
+            GameState::from_ptn_list(5, 0, &state.to_ptn()).unwrap(),
+        ] {
+            assert_eq!(copy.first_player(), Player::Black);
+            assert_eq!(copy.current_player(), Player::White);
+            assert_eq!(copy.ply(), 1);
+            assert_eq!(copy.to_tps(), state.to_tps());
+        }
+
+        // The swap and the opening rule then cover White
+        let wall = Turn::from_ptn("Se5", Player::White, 5).unwrap();
+        assert_eq!(state.try_apply_turn(&wall), Err(TurnError::OpeningNotFlat));
+        assert!(state.apply_ptn("e5").is_ok());
+        assert_eq!(owner(&state, "e5"), Player::Black);
+        assert!(state.apply_ptn("c3").is_ok());
+        assert_eq!(owner(&state, "c3"), Player::Black);
+        assert_eq!(state.current_player(), Player::White);
+        assert_eq!(state.move_number(), 3);
+        assert_eq!(state.to_saved().restore(), Some(state));
+    }
+
+    #[test]
+    fn a_turn_that_takes_too_long_is_not_played() {
+        let secs = Duration::from_secs;
+        let mut state = GameState::new(5);
+        state.set_clock(Some(Clock::new(secs(10), secs(2))));
+        let a1 = Turn::from_ptn("a1", Player::White, 5).unwrap();
+        assert_eq!(
+            state.try_apply_timed_turn(&a1, secs(10)),
+            Err(TurnError::GameOver)
+        );
+        assert_eq!(state.result(), GameResult::TimeoutWin(Player::Black));
+        assert_eq!(state.ply(), 0);
+        assert!(state.board()[Loc { row: 4, col: 0 }].is_empty());
+
+        // An illegal turn doesn't use any time
+        let mut state = GameState::new(5);
+        state.set_clock(Some(Clock::new(secs(10), secs(2))));
+        let wall = Turn::from_ptn("Sa1", Player::White, 5).unwrap();
+        assert_eq!(
+            state.try_apply_timed_turn(&wall, secs(20)),
+            Err(TurnError::OpeningNotFlat)
+        );
+        assert_eq!(state.result(), GameResult::Ongoing);
+        assert_eq!(state.clock().unwrap().remaining(Player::White), secs(10));
+    }
+
+    #[test]
+    fn no_suicide_forbids_uncovering_the_opponents_road() {
+        let tps = "x5/x5/x5/x5/2,2,2,2,21 1 5";
+        let uncover = Turn::from_ptn("e1+", Player::White, 5).unwrap();
+        let mut standard = GameState::from_tps(tps).unwrap();
+        assert!(standard.legal_turns().contains(&uncover));
+        assert_eq!(standard.try_apply_turn(&uncover), Ok(()));
+        assert_eq!(standard.result(), GameResult::RoadWin(Player::Black));
+
+        let mut state = GameState::from_tps(tps).unwrap();
+        state.win_rules = WinRules {
+            roads_only: false,
+            no_suicide: true,
+        };
+        assert!(!state.legal_turns().contains(&uncover));
+        assert_eq!(
+            state.try_apply_turn(&uncover),
+            Err(TurnError::CompletesOpponentsRoad)
+        );
+        assert_eq!(state.ply(), 8);
+        // Placing can't build the opponent's road, so every placement is allowed
+        assert_eq!(
+            state.legal_placements(),
+            GameState::from_tps(tps).unwrap().legal_placements()
+        );
+        assert!(state.apply_turn(&Turn::from_ptn("e1<", Player::White, 5).unwrap()));
+    }
+
+    #[test]
+    fn empty_square_parity_flips_as_the_board_fills() {
+        let mut state = GameState::new(3);
+        // 9 squares, so White places last
+        assert_eq!(state.empty_square_parity(), 1);
+        for (row, col) in [(0, 1), (0, 0), (0, 2), (1, 0)] {
+            let parity = state.empty_square_parity();
+            assert!(state.apply_turn(&place(row, col, state.current_player(), StoneType::Flat)));
+            assert_eq!(state.empty_square_parity(), -parity);
+        }
+        // Spreading a stone off its square leaves one more to fill
+        assert_eq!(state.empty_square_parity(), 1);
+        assert!(state.apply_turn(&Turn::from_ptn("a3>", Player::White, 3).unwrap()));
+        assert_eq!(state.empty_square_parity(), -1);
+        let last_square = GameState::from_tps("1,2,1/2,1,2/1,2,x 1 5").unwrap();
+        assert_eq!(last_square.empty_square_parity(), 1);
+        assert_eq!(
+            GameState::from_tps("1,2,1/2,1,2/1,2,1 2 5")
+                .unwrap()
+                .empty_square_parity(),
+            0
+        );
+    }
+
+    #[test]
+    fn placing_takes_from_the_reserve() {
+        let mut state = GameState::new(5);
+        let full = Reserve { reg: 21, cap: 1 };
+        assert_eq!(state.reserve(Player::White), full);
+        for ptn in ["a1", "e5", "Cc3", "Sd4"] {
+            let turn = Turn::from_ptn(ptn, state.current_player(), 5).unwrap();
+            assert!(state.apply_turn(&turn), "{ptn}");
+        }
+        assert_eq!(state.reserve(Player::White), Reserve { reg: 20, cap: 0 });
+        assert_eq!(state.reserve(Player::Black), Reserve { reg: 19, cap: 1 });
+        // Spreads don't use up pieces
+        assert!(state.apply_turn(&Turn::from_ptn("c3-", Player::White, 5).unwrap()));
+        assert_eq!(state.reserve(Player::White), Reserve { reg: 20, cap: 0 });
+    }
+
+    #[test]
+    fn players_take_turns_in_order() {
+        assert_eq!(Player::all(), [Player::White, Player::Black]);
+        for player in Player::all() {
+            assert_ne!(player.next(), player);
+            assert_eq!(player.next().next(), player);
+        }
+        assert_eq!(Player::White.next(), Player::Black);
+
+        let mut reserves = Reserves::new(Reserve::starting(5).unwrap());
+        reserves[Player::Black].cap = 0;
+        assert_eq!(reserves[Player::White].cap, 1);
+        assert_eq!(
+            reserves.values().filter(|reserve| reserve.cap == 0).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn unmake_restores_everything() {
+        random_games(7, 30, |before, turn, _| {
+            let mut before = before.clone();
+            before.set_clock(Some(Clock::new(
+                Duration::from_secs(60),
+                Duration::from_secs(1),
+            )));
+            let mut state = before.clone();
+            let unmake = state.make(turn).unwrap();
+            assert_eq!(state.history().len(), before.history().len());
+            state.unmake(unmake);
+            assert!(
+                state == before,
+                "{} in\n{before}",
+                turn.to_ptn(before.board.size())
+            );
+        });
+
+        // Including a finished game's result
+        let mut state = GameState::from_tps("x5/x5/x5/x5/1,1,1,1,x 1 5").unwrap();
+        let before = state.clone();
+        let unmake = state
+            .make(&Turn::from_ptn("e1", Player::White, 5).unwrap())
+            .unwrap();
+        assert_eq!(state.result(), GameResult::RoadWin(Player::White));
+        state.unmake(unmake);
+        assert!(state == before);
+        assert_eq!(
+            state.make(&Turn::from_ptn("e1", Player::Black, 5).unwrap()),
+            Err(TurnError::WrongPlayer)
+        );
+    }
+
+    #[test]
+    fn saved_game_round_trip() {
+        let mut state = GameState::with_komi(5, 4);
+        assert!(state.apply_turn(&place(1, 0, Player::White, StoneType::Flat)));
+        assert!(state.apply_turn(&place(0, 0, Player::Black, StoneType::Flat)));
+        assert!(state.apply_turn(&place(2, 2, Player::White, StoneType::Standing)));
+        assert!(state.apply_turn(&Turn::Move {
+            loc: Loc { row: 1, col: 0 },
+            player: Player::Black,
+            dir: Dir::North,
+            total: 1,
+            drops: vec![1],
+        }));
+        let json = serde_json::to_string(&state.to_saved()).unwrap();
+        let saved: SavedGame = serde_json::from_str(&json).unwrap();
+        let mut loaded = saved.restore().unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.undo());
+        assert!(serde_json::from_str::<SavedGame>("{\"size\": 5").is_err());
+    }
+
+    #[test]
+    fn saved_game_keeps_its_starting_position() {
+        let mut state = GameState::from_tps("x5/x5/x2,21,x2/x5/x5 1 3").unwrap();
+        assert!(state.apply_ptn("a1").is_ok());
+        let saved = state.to_saved();
+        assert_eq!(saved.start.as_deref(), Some("x5/x5/x2,21,x2/x5/x5 1 3"));
+        let mut loaded = saved.restore().unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.undo());
+        assert!(!loaded.undo());
+
+        // Games from an empty board don't need one
+        assert_eq!(GameState::new(5).to_saved().start, None);
+        let mut wrong_size = saved;
+        wrong_size.size = 6;
+        assert_eq!(wrong_size.restore(), None);
+    }
+
+    #[test]
+    fn spreads_from_height_3_stack() {
+        let mut board = Board::new(5);
+        let white = Stone {
+            owner: Player::White,
+            typ: StoneType::Flat,
+        };
+        let black = Stone {
+            owner: Player::Black,
+            typ: StoneType::Flat,
+        };
+        board.set_stack(Loc { row: 2, col: 2 }, vec![white, black, white]);
+        board.set_stack(Loc { row: 0, col: 0 }, vec![black, black, white]);
+        // Two squares in each direction: 1 way to carry one, 2 to carry two
+        // ([2], [1, 1]) and 3 to carry three ([3], [1, 2], [2, 1])
+        let center = spread_turns(&board, Loc { row: 2, col: 2 }, Player::White, 5);
+        assert_eq!(center.len(), 4 * 6);
+        // Only east and south, with room for every partition of up to 3 stones
+        let corner = spread_turns(&board, Loc { row: 0, col: 0 }, Player::White, 5);
+        assert_eq!(corner.len(), 2 * (1 + 2 + 4));
+        // Carry limit applies
+        let limited = spread_turns(&board, Loc { row: 0, col: 0 }, Player::White, 2);
+        assert_eq!(limited.len(), 2 * (1 + 2));
+        assert!(spread_turns(&board, Loc { row: 0, col: 0 }, Player::Black, 5).is_empty());
+    }
+
+    #[test]
+    fn spreads_never_leave_the_board() {
+        let size = 5;
+        let mut board = Board::new(size);
+        let white = Stone {
+            owner: Player::White,
+            typ: StoneType::Flat,
+        };
+        for index in 0..size * size {
+            board.set_stack(Loc::from_index(index, size), vec![white; 1 + index % 5]);
+        }
+        for index in 0..size * size {
+            let loc = Loc::from_index(index, size);
+            let generated = spread_turns(&board, loc, Player::White, size);
+            for turn in &generated {
+                let Turn::Move {
+                    loc: _,
+                    player: _,
+                    dir,
+                    total: _,
+                    drops,
+                } = turn
+                else {
+                    panic!("{turn:?} isn't a spread");
+                };
+                assert!(board.valid_loc(loc.move_in_by(*dir, drops.len())));
+            }
+            // Everything the rules allow, found without knowing about edges
+            let validated = Dir::ALL
+                .into_iter()
+                .flat_map(|dir| {
+                    (1..=size).flat_map(move |total| {
+                        partitions(total, size)
+                            .into_iter()
+                            .map(move |drops| Turn::Move {
+                                loc,
+                                player: Player::White,
+                                dir,
+                                total,
+                                drops,
+                            })
+                    })
+                })
+                .filter(|turn| board.valid_turn(turn))
+                .count();
+            assert_eq!(generated.len(), validated, "spreads from {loc:?}");
+        }
+    }
+
+    #[test]
+    fn legal_move_count_matches_legal_turns() {
+        let count_matches = |state: &GameState| {
+            assert_eq!(
+                state.legal_move_count(),
+                state.legal_turns().len(),
+                "in\n{state}"
+            );
+        };
+        for tps in [
+            "1C,2S,x4/2S,x5/x6/x6/x6/x6 2 4",
+            "2121212C,x,2S,x4/x,21,2C,x4/x,1S,x5/x7/x7/x7/x7 1 12",
+            "x5/x5/x5/x5/x5 1 1",
+        ] {
+            count_matches(&GameState::from_tps(tps).unwrap());
+        }
+        let games = random_games(11, 20, |before, _, _| {
+            for no_suicide in [false, true] {
+                let mut state = before.clone();
+                state.win_rules.no_suicide = no_suicide;
+                count_matches(&state);
+            }
+        });
+        for state in games {
+            assert_eq!(state.legal_move_count(), 0);
+        }
+    }
+
+    #[test]
+    fn trapped_capstone_has_no_mobility() {
+        // Walls don't trap a capstone, it can always crush them alone
+        let state = GameState::from_tps("1C,2S,x4/2S,x5/x6/x6/x6/x6 2 4").unwrap();
+        assert_eq!(state.capstone_mobility(Player::White), 2);
+        // Capstones and the edge of the board do
+        let state = GameState::from_tps("1C,2C,x6/2C,x7/x8/x8/x8/x8/x8/x8 1 4").unwrap();
+        assert_eq!(state.capstone_mobility(Player::White), 0);
+        assert_eq!(state.capstone_mobility(Player::Black), 2 * 2);
+        assert_eq!(GameState::new(5).capstone_mobility(Player::White), 0);
+    }
+
+    #[test]
+    fn flats_needed_for_flat_win() {
+        let mut state = GameState::new(5);
+        for (row, col) in [(0, 0), (4, 4), (0, 1), (4, 3)] {
+            let player = state.current_player;
+            assert!(state.apply_turn(&place(row, col, player, StoneType::Flat)));
+        }
+        // Even flats, but White gets the extra empty square
+        assert_eq!(state.flats_to_flat_win(Player::White), 0);
+        assert_eq!(state.flats_to_flat_win(Player::Black), 2);
+        assert!(state.apply_turn(&place(0, 2, Player::White, StoneType::Flat)));
+        // White is a flat ahead and the empty squares split evenly
+        assert_eq!(state.flats_to_flat_win(Player::White), 0);
+        assert_eq!(state.flats_to_flat_win(Player::Black), 2);
+
+        let mut state = GameState::with_komi(5, 3);
+        for (row, col) in [(0, 0), (4, 4), (0, 1), (4, 3), (0, 2)] {
+            let player = state.current_player;
+            assert!(state.apply_turn(&place(row, col, player, StoneType::Flat)));
+        }
+        // 1.5 komi puts Black half a flat ahead
+        assert_eq!(state.flats_to_flat_win(Player::White), 1);
+        assert_eq!(state.flats_to_flat_win(Player::Black), 0);
+    }
+
+    #[test]
+    fn scenario_setup() {
+        let mut board = Board::new(6);
+        let loc = Loc { row: 3, col: 2 };
+        let flat = |owner| Stone {
+            owner,
+            typ: StoneType::Flat,
+        };
+        board.set_stack(loc, vec![flat(Player::White), flat(Player::Black)]);
+        for _ in 0..5 {
+            board.place_stone(loc, flat(Player::White));
+        }
+        board.place_stone(
+            loc,
+            Stone {
+                owner: Player::Black,
+                typ: StoneType::Capstone,
+            },
+        );
+        assert_eq!(board.stack_height(loc), 8);
+        assert_eq!(board.stack_height(Loc { row: 2, col: 2 }), 0);
+        assert_eq!(board[loc].last().unwrap().typ, StoneType::Capstone);
+        board.set_stack(loc, Vec::new());
+        assert_eq!(board.stack_height(loc), 0);
+    }
+
+    fn fill_around_column(last: StoneType) -> GameState {
+        let mut state = GameState::new(3);
+        for (row, col) in [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (1, 2),
+            (1, 1),
+            (2, 1),
+            (2, 0),
+            (2, 2),
+        ] {
+            let player = state.current_player;
+            assert!(state.apply_turn(&place(row, col, player, StoneType::Flat)));
+            assert_eq!(state.result(), GameResult::Ongoing);
+        }
+        assert!(state.apply_turn(&place(1, 0, Player::White, last)));
+        state
+    }
+
+    #[test]
+    fn wall_blocks_road() {
+        // A white wall between two white flats in the first column
+        let state = fill_around_column(StoneType::Standing);
+        assert!(!state.board.check_road(Player::White));
+        // The wall still fills the board, and doesn't count as a flat
+        assert!(state.board.is_full());
+        assert_eq!(state.board.flat_count(Player::White), 4);
+        assert_eq!(state.board.flat_count(Player::Black), 4);
+        assert_eq!(state.result(), GameResult::Draw);
+    }
+
+    #[test]
+    fn flat_completes_road_instead_of_wall() {
+        let state = fill_around_column(StoneType::Flat);
+        assert_eq!(state.result(), GameResult::RoadWin(Player::White));
+    }
+
+    #[test]
+    fn road_beats_flats_when_board_fills() {
+        // Black fills the last square with a road, though White has more flats
+        let mut state = GameState::from_tps("2,1,2S,2S/x,1,2S,1/2,1,2S,1/2,2S,2S,1 2 9").unwrap();
+        assert!(state.apply_turn(&place(1, 0, Player::Black, StoneType::Flat)));
+        assert!(state.board.is_full());
+        assert_eq!(state.flat_result(0), GameResult::FlatWin(Player::White));
+        assert_eq!(state.result(), GameResult::RoadWin(Player::Black));
+    }
+
+    #[test]
+    fn mover_wins_double_road() {
+        // Moving White's stone off c2 completes both White's and Black's roads
+        let mut state = GameState::from_tps("x,1,2/x2,21/x,1,2 1 4").unwrap();
+        assert!(state.apply_turn(&Turn::from_ptn("c2<", Player::White, 3).unwrap()));
+        assert!(state.board.check_road(Player::White));
+        assert!(state.board.check_road(Player::Black));
+        assert_eq!(state.result(), GameResult::RoadWin(Player::White));
+    }
+
+    #[test]
+    fn result_at_different_komi() {
+        // Checkerboard, White has 5 flats to Black's 4
+        let mut state = GameState::new(3);
+        for row in 0..3 {
+            for col in 0..3 {
+                let player = state.current_player;
+                assert!(state.apply_turn(&place(row, col, player, StoneType::Flat)));
+            }
+        }
+        assert_eq!(state.result(), GameResult::FlatWin(Player::White));
+        assert_eq!(
+            state.result_with_komi(0),
+            GameResult::FlatWin(Player::White)
+        );
+        assert_eq!(
+            state.result_with_komi(1),
+            GameResult::FlatWin(Player::White)
+        );
+        assert_eq!(state.result_with_komi(2), GameResult::Draw);
+        assert_eq!(
+            state.result_with_komi(3),
+            GameResult::FlatWin(Player::Black)
+        );
+        assert_eq!(state.komi(), 0);
+        assert_eq!(state.result(), GameResult::FlatWin(Player::White));
+
+        let road = fill_around_column(StoneType::Flat);
+        assert_eq!(
+            road.result_with_komi(10),
+            GameResult::RoadWin(Player::White)
+        );
+        assert_eq!(GameState::new(3).result_with_komi(4), GameResult::Ongoing);
+    }
+
+    #[test]
+    fn loc_index_round_trip() {
+        for index in 0..25 {
+            assert_eq!(Loc::from_index(index, 5).to_index(5), index);
+        }
+        assert_eq!(Loc::from_index(0, 5), Loc { row: 0, col: 0 });
+        assert_eq!(Loc::from_index(4, 5), Loc { row: 0, col: 4 });
+        assert_eq!(Loc::from_index(5, 5), Loc { row: 1, col: 0 });
+        assert_eq!(Loc::from_index(24, 5), Loc { row: 4, col: 4 });
+    }
+
+    #[test]
+    fn no_legal_turns_ends_game() {
+        // Can't happen under the standard rules: Black has nothing to place and
+        // White controls every stack. Playing into this position would end the
+        // game on reserves first, so it's built by hand, still ongoing.
+        let mut state = GameState::new(3);
+        let flat = |owner| Stone {
+            owner,
+            typ: StoneType::Flat,
+        };
+        for i in 0..8 {
+            state.board.set_stack(
+                Loc::from_index(i, 3),
+                vec![flat(Player::Black), flat(Player::White)],
+            );
+        }
+        state.reserves[Player::Black] = Reserve { reg: 0, cap: 0 };
+        state.current_player = Player::Black;
+        assert!(state.legal_turns().is_empty());
+        assert_eq!(state.result(), GameResult::Ongoing);
+        state.end_if_stuck();
+        assert_eq!(state.result(), GameResult::FlatWin(Player::White));
+    }
 }
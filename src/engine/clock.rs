@@ -0,0 +1,71 @@
+//! Chess clocks for timed games. The engine has no sense of time itself, so
+//! whatever is running the game says how much has passed with
+//! `GameState::tick` or `GameState::try_apply_timed_turn`.
+
+use std::time::Duration;
+
+use super::Player;
+
+/// How much thinking time each player has left, and how much they get back
+/// after each of their turns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    pub white: Duration,
+    pub black: Duration,
+    pub increment: Duration,
+}
+
+impl Clock {
+    /// Both players starting with `time`
+    pub fn new(time: Duration, increment: Duration) -> Clock {
+        Clock {
+            white: time,
+            black: time,
+            increment,
+        }
+    }
+
+    pub fn remaining(&self, player: Player) -> Duration {
+        match player {
+            Player::White => self.white,
+            Player::Black => self.black,
+        }
+    }
+
+    fn remaining_mut(&mut self, player: Player) -> &mut Duration {
+        match player {
+            Player::White => &mut self.white,
+            Player::Black => &mut self.black,
+        }
+    }
+
+    /// Takes `elapsed` off `player`'s time, returning whether they have run
+    /// out
+    pub fn tick(&mut self, player: Player, elapsed: Duration) -> bool {
+        let remaining = self.remaining_mut(player);
+        *remaining = remaining.saturating_sub(elapsed);
+        remaining.is_zero()
+    }
+
+    pub fn add_increment(&mut self, player: Player) {
+        let increment = self.increment;
+        *self.remaining_mut(player) += increment;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_down_to_zero() {
+        let mut clock = Clock::new(Duration::from_secs(10), Duration::from_secs(2));
+        assert!(!clock.tick(Player::White, Duration::from_secs(4)));
+        assert_eq!(clock.remaining(Player::White), Duration::from_secs(6));
+        assert_eq!(clock.remaining(Player::Black), Duration::from_secs(10));
+        clock.add_increment(Player::White);
+        assert_eq!(clock.remaining(Player::White), Duration::from_secs(8));
+        assert!(clock.tick(Player::White, Duration::from_secs(60)));
+        assert_eq!(clock.remaining(Player::White), Duration::ZERO);
+    }
+}
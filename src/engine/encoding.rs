@@ -0,0 +1,277 @@
+//! A compact binary encoding of boards, for saving and sending positions
+//! without the overhead of text.
+//!
+//! The first byte is the board size. Each square follows in row-major order
+//! as a byte holding the stack height, then the stack's stones from bottom to
+//! top packed two to a byte, low nibble first. A stone's nibble is its owner
+//! in the upper 2 bits and its type in the lower 2.
+//!
+//! A share code is a game's board in this encoding followed by its ply as a
+//! little-endian base-128 varint, written in URL-safe base64 without padding.
+//! The player to move follows from the ply, as it does in TPS, which counts
+//! White's skipped first turn in a game Black started.
+
+use super::{Board, GameState, Loc, Player, Reserve, Stone, StoneType};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+impl Stone {
+    fn to_nibble(self) -> u8 {
+        let owner = match self.owner {
+            Player::White => 0,
+            Player::Black => 1,
+        };
+        let typ = match self.typ {
+            StoneType::Flat => 0,
+            StoneType::Standing => 1,
+            StoneType::Capstone => 2,
+        };
+        owner << 2 | typ
+    }
+
+    fn from_nibble(nibble: u8) -> Option<Stone> {
+        let owner = match nibble >> 2 {
+            0 => Player::White,
+            1 => Player::Black,
+            _ => return None,
+        };
+        let typ = match nibble & 0b11 {
+            0 => StoneType::Flat,
+            1 => StoneType::Standing,
+            2 => StoneType::Capstone,
+            _ => return None,
+        };
+        Some(Stone { owner, typ })
+    }
+}
+
+impl Board {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let size = self.size();
+        let mut bytes = vec![size as u8];
+        for i in 0..size * size {
+            let stack = &self[Loc::from_index(i, size)];
+            bytes.push(stack.len() as u8);
+            for pair in stack.chunks(2) {
+                let high = pair.get(1).map_or(0, |stone| stone.to_nibble());
+                bytes.push(high << 4 | pair[0].to_nibble());
+            }
+        }
+        bytes
+    }
+
+    /// Reads a board written by `to_bytes`, returning `None` if the size isn't
+    /// playable, a stack is taller than all the stones in a game, or the bytes
+    /// don't hold exactly one board
+    pub fn from_bytes(bytes: &[u8]) -> Option<Board> {
+        let (&size, mut rest) = bytes.split_first()?;
+        let size = usize::from(size);
+        let reserve = Reserve::starting(size)?;
+        let max_height = 2 * usize::from(reserve.reg + reserve.cap);
+
+        let mut board = Board::new(size);
+        for i in 0..size * size {
+            let (&height, after_height) = rest.split_first()?;
+            let height = usize::from(height);
+            if height > max_height {
+                return None;
+            }
+            let packed_len = height.div_ceil(2);
+            if after_height.len() < packed_len {
+                return None;
+            }
+            let (packed, after_stack) = after_height.split_at(packed_len);
+            let mut stack = Vec::with_capacity(height);
+            for (j, byte) in packed.iter().enumerate() {
+                stack.push(Stone::from_nibble(byte & 0xf)?);
+                if 2 * j + 1 < height {
+                    stack.push(Stone::from_nibble(byte >> 4)?);
+                } else if byte >> 4 != 0 {
+                    return None;
+                }
+            }
+            board.set_stack(Loc::from_index(i, size), stack);
+            rest = after_stack;
+        }
+        if !(rest.is_empty()) {
+            return None;
+        }
+        Some(board)
+    }
+}
+
+impl GameState {
+    /// The position as a short code that's safe to paste anywhere, including
+    /// URLs. It keeps the board and the player to move, but not the history.
+    pub fn to_share_code(&self) -> String {
+        let mut bytes = self.board.to_bytes();
+        let mut ply = self.notation_ply();
+        while ply >= 0x80 {
+            bytes.push(ply as u8 | 0x80);
+            ply >>= 7;
+        }
+        bytes.push(ply as u8);
+        to_base64(&bytes)
+    }
+
+    /// Loads a position from `to_share_code`, returning `None` if the code
+    /// isn't one
+    pub fn from_share_code(code: &str) -> Option<GameState> {
+        let bytes = from_base64(code)?;
+        // No byte of a board has its top bit set, so the ply starts after the
+        // last one without it, not counting the ply's own last byte
+        let (last, rest) = bytes.split_last()?;
+        if last & 0x80 != 0 {
+            return None;
+        }
+        let start = rest
+            .iter()
+            .rposition(|byte| byte & 0x80 == 0)
+            .map_or(0, |i| i + 1);
+        let (board, varint) = bytes.split_at(start);
+        let mut ply = 0usize;
+        for (i, byte) in varint.iter().enumerate() {
+            let bits = usize::from(byte & 0x7f).checked_shl(7 * i as u32)?;
+            if bits >> (7 * i) != usize::from(byte & 0x7f) {
+                return None;
+            }
+            ply |= bits;
+        }
+        let board = Board::from_bytes(board)?;
+        let current_player = match ply % 2 {
+            0 => Player::White,
+            _ => Player::Black,
+        };
+        GameState::from_position(board, current_player, ply).ok()
+    }
+}
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut code = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            code.push(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    code
+}
+
+/// Reads unpadded URL-safe base64, returning `None` if `code` has anything
+/// else in it or stops partway through a byte
+fn from_base64(code: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(code.len() * 3 / 4);
+    for chunk in code.as_bytes().chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (i, ch) in chunk.iter().enumerate() {
+            let value = BASE64.iter().position(|b| b == ch)?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((bits >> (16 - 8 * i)) as u8);
+        }
+        // Leftover bits past the last byte must be zero, so every code is
+        // the only one for its bytes
+        if bits << (8 * (chunk.len() - 1)) & 0xff_ffff != 0 {
+            return None;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+    use super::*;
+    use crate::engine::tests::random_games;
+
+    fn random_board(rng: &mut SmallRng) -> Board {
+        let size = 3 + rng.gen_range(0..6);
+        let mut board = Board::new(size);
+        for i in 0..size * size {
+            let stack = (0..rng.gen_range(0..8))
+                .map(|_| Stone {
+                    owner: [Player::White, Player::Black][rng.gen_range(0..2)],
+                    typ: [StoneType::Flat, StoneType::Standing, StoneType::Capstone]
+                        [rng.gen_range(0..3)],
+                })
+                .collect();
+            board.set_stack(Loc::from_index(i, size), stack);
+        }
+        board
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..500 {
+            let board = random_board(&mut rng);
+            assert_eq!(Board::from_bytes(&board.to_bytes()), Some(board));
+        }
+    }
+
+    #[test]
+    fn bytes_errors() {
+        let mut bytes = Board::new(3).to_bytes();
+        assert_eq!(bytes.len(), 10);
+        assert_eq!(Board::from_bytes(&bytes), Some(Board::new(3)));
+        assert_eq!(Board::from_bytes(&[]), None);
+        assert_eq!(Board::from_bytes(&bytes[..9]), None);
+        bytes.push(0);
+        assert_eq!(Board::from_bytes(&bytes), None);
+
+        assert_eq!(Board::from_bytes(&Board::new(2).to_bytes()), None);
+
+        // A stack of 1 has nothing in the upper nibble
+        let mut bytes = Board::new(3).to_bytes();
+        bytes[1] = 1;
+        bytes.insert(2, 0x00);
+        assert!(Board::from_bytes(&bytes).is_some());
+        bytes[2] = 0x10;
+        assert_eq!(Board::from_bytes(&bytes), None);
+        bytes[2] = 0x03;
+        assert_eq!(Board::from_bytes(&bytes), None);
+
+        // Taller than both players' stones put together
+        let mut bytes = Board::new(3).to_bytes();
+        bytes[1] = 21;
+        bytes.splice(2..2, [0; 11]);
+        assert_eq!(Board::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn share_code_round_trip() {
+        random_games(2, 40, |_, _, state| {
+            let code = state.to_share_code();
+            assert!(
+                code.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'),
+                "{code}"
+            );
+            let shared = GameState::from_share_code(&code).unwrap();
+            assert_eq!(shared.to_tps(), state.to_tps());
+            assert_eq!(shared.result(), state.result());
+        });
+    }
+
+    #[test]
+    fn share_code_errors() {
+        let code = GameState::new(6).to_share_code();
+        assert_eq!(code.len(), 51);
+        assert!(GameState::from_share_code(&code).is_some());
+        assert!(GameState::from_share_code("").is_none());
+        assert!(GameState::from_share_code(&code[..code.len() - 1]).is_none());
+        assert!(GameState::from_share_code(&code.replace('A', "+")).is_none());
+        // Flipping the unused bits of the last character
+        let mut tampered = code.clone();
+        tampered.pop();
+        tampered.push('B');
+        assert!(GameState::from_share_code(&tampered).is_none());
+    }
+}
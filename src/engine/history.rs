@@ -0,0 +1,134 @@
+//! A game's turns kept so that any earlier position can be looked at again,
+//! without holding on to a copy of the game for every ply.
+//!
+//! Every `KEYFRAME_INTERVAL` turns the position is kept, without its undo
+//! history. Going to a ply starts from the last keyframe at or before it and
+//! replays the turns after that, so it never replays more than
+//! `KEYFRAME_INTERVAL - 1` of them.
+
+use super::{GameState, Turn, TurnError};
+
+/// How many turns apart keyframes are
+const KEYFRAME_INTERVAL: usize = 16;
+
+#[derive(Clone)]
+pub struct GameHistory {
+    /// Every turn played from the first keyframe
+    turns: Vec<Turn>,
+    keyframes: Vec<GameState>,
+    /// The latest position, to play the next turn from
+    latest: GameState,
+}
+
+impl GameHistory {
+    /// A history starting from `start`, which is kept without its own history
+    pub fn new(start: GameState) -> Self {
+        let start = start.without_history();
+        GameHistory {
+            turns: Vec::new(),
+            keyframes: vec![start.clone()],
+            latest: start,
+        }
+    }
+
+    /// The ply of the first position
+    pub fn start_ply(&self) -> usize {
+        self.keyframes[0].ply()
+    }
+
+    /// The ply of the latest position
+    pub fn latest_ply(&self) -> usize {
+        self.latest.ply()
+    }
+
+    pub fn latest(&self) -> &GameState {
+        &self.latest
+    }
+
+    /// Plays `turn` from the latest position
+    pub fn push(&mut self, turn: &Turn) -> Result<(), TurnError> {
+        self.latest.try_apply_turn(turn)?;
+        self.latest.history.clear();
+        self.turns.push(turn.clone());
+        if self.turns.len().is_multiple_of(KEYFRAME_INTERVAL) {
+            self.keyframes.push(self.latest.clone());
+        }
+        Ok(())
+    }
+
+    /// The position at `ply`, or `None` if it's before the start or after the
+    /// latest position. The turns since the keyframe it was replayed from can
+    /// be undone, but nothing earlier.
+    pub fn goto(&self, ply: usize) -> Option<GameState> {
+        let played = ply.checked_sub(self.start_ply())?;
+        if played > self.turns.len() {
+            return None;
+        }
+        let keyframe = played / KEYFRAME_INTERVAL;
+        let mut state = self.keyframes[keyframe].clone();
+        for turn in &self.turns[keyframe * KEYFRAME_INTERVAL..played] {
+            let played = state.apply_turn(turn);
+            debug_assert!(played, "history replayed an illegal turn");
+        }
+        Some(state)
+    }
+}
+
+impl GameState {
+    fn without_history(mut self) -> GameState {
+        self.history.clear();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+    use super::*;
+    use crate::engine::{Player, tests::random_games};
+
+    #[test]
+    fn goto_matches_replaying_from_the_start() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for game in random_games(1, 10, |_, _, _| {}) {
+            let size = game.board().size();
+            let moves: Vec<Turn> = game
+                .history()
+                .iter()
+                .map(|record| record.turn.clone())
+                .collect();
+            let mut history = GameHistory::new(GameState::new(size));
+            for turn in &moves {
+                assert_eq!(history.push(turn), Ok(()));
+            }
+            assert_eq!(history.keyframes.len(), moves.len() / KEYFRAME_INTERVAL + 1);
+            assert_eq!(history.latest_ply(), moves.len());
+
+            for _ in 0..20 {
+                let ply = rng.gen_range(0..=moves.len());
+                let naive = GameState::from_moves(size, 0, &moves[..ply]).unwrap();
+                let position = history.goto(ply).unwrap();
+                assert!(position.board() == naive.board(), "ply {ply}");
+                assert_eq!(position.to_tps(), naive.to_tps());
+                assert_eq!(position.result(), naive.result());
+            }
+            assert!(history.goto(moves.len() + 1).is_none());
+        }
+    }
+
+    #[test]
+    fn history_from_a_position() {
+        let start = GameState::from_tps("x5/x5/x2,1,x2/x5/x4,2 1 3").unwrap();
+        let mut history = GameHistory::new(start.clone());
+        let turn = Turn::from_ptn("a1", Player::White, 5).unwrap();
+        assert_eq!(history.push(&turn), Ok(()));
+        assert_eq!(history.start_ply(), 4);
+        assert!(history.goto(3).is_none());
+        assert_eq!(history.goto(4).unwrap().to_tps(), start.to_tps());
+        assert_eq!(history.goto(5).unwrap().to_tps(), history.latest().to_tps());
+        // Illegal turns aren't kept
+        assert_eq!(history.push(&turn), Err(TurnError::WrongPlayer));
+        assert_eq!(history.latest_ply(), 5);
+    }
+}
@@ -0,0 +1,1136 @@
+//! Parsing and formatting of the standard Tak notations: algebraic squares
+//! (`a1`), PTN moves (`3c3>12`) and TPS positions
+//! (`x3,12,2S/x5/x5/x5/x5 1 8`).
+
+use std::fmt;
+
+use super::{
+    Board, Dir, GameResult, GameState, Loc, MoveRecord, Player, Reserve, Reserves, Roads, Stone,
+    StoneType, Turn, TurnError,
+};
+
+/// Why some notation couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// `ch`, at byte offset `pos`, doesn't fit the notation
+    UnexpectedChar { pos: usize, ch: char },
+    /// The input ended before the notation was complete
+    UnexpectedEnd,
+    /// A square that isn't on the board
+    OutOfBounds,
+    /// A spread whose drops don't add up to the number of stones picked up,
+    /// or that drops no stones on a square
+    BadDrops,
+    /// A TPS square that isn't `x`, `x<count>` or a stack like `121S`
+    BadStackToken(String),
+    /// A TPS row (counting from the top) with `width` squares instead of `size`
+    WrongRowWidth {
+        row: usize,
+        width: usize,
+        size: usize,
+    },
+    /// A TPS board whose number of rows isn't a playable size
+    BadSize(usize),
+    /// A TPS position with more of `player`'s stones than a game has
+    TooManyStones(Player),
+    /// A TPS move number that isn't a positive number
+    BadMoveNumber(String),
+    /// A PTN move in a game being replayed that can't be read, or can't be
+    /// played in the game so far
+    BadMove(Box<ReplayError>),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar { pos, ch } => write!(f, "unexpected '{ch}' at position {pos}"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of input"),
+            Self::OutOfBounds => write!(f, "square is not on the board"),
+            Self::BadDrops => write!(f, "drops don't match the number of stones carried"),
+            Self::BadStackToken(token) => write!(f, "'{token}' is not a valid square"),
+            Self::WrongRowWidth { row, width, size } => {
+                write!(f, "row {} has {width} squares instead of {size}", row + 1)
+            }
+            Self::BadSize(size) => write!(f, "board size {size} is not between 3 and 8"),
+            Self::TooManyStones(player) => write!(f, "player {player} has too many stones"),
+            Self::BadMoveNumber(number) => write!(f, "'{number}' is not a valid move number"),
+            Self::BadMove(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A move in a replayed game that couldn't be read or played
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayError {
+    /// How many moves were played before it, so the first move is ply 0
+    pub ply: usize,
+    /// The move as it was written
+    pub ptn: String,
+    pub cause: ApplyPtnError,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ply = self.ply + 1;
+        match &self.cause {
+            ApplyPtnError::Parse(err) => {
+                write!(f, "'{}' (ply {ply}) can't be read: {err}", self.ptn)
+            }
+            ApplyPtnError::Illegal(err) => {
+                write!(f, "'{}' (ply {ply}) is not a legal move: {err}", self.ptn)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Why `GameState::apply_ptn` couldn't play a move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyPtnError {
+    Parse(ParseError),
+    Illegal(TurnError),
+}
+
+impl fmt::Display for ApplyPtnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::Illegal(err) => write!(f, "not a legal move: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ApplyPtnError {}
+
+/// How closely PTN moves have to follow the standard
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PtnStyle {
+    /// Anything that reads as a move, e.g. `1a1>1` for `a1>`
+    #[default]
+    Lenient,
+    /// Counts and drops are only written when they aren't implied: a count
+    /// of 1, or a single drop of everything picked up, is rejected
+    Strict,
+}
+
+/// Walks through a string one character at a time, keeping track of the
+/// position for error reporting
+struct Cursor<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Cursor { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    /// Consumes a single digit, if there is one
+    fn digit(&mut self) -> Option<usize> {
+        let digit = self.peek()?.to_digit(10)?;
+        self.bump();
+        Some(digit as usize)
+    }
+
+    /// The error for whatever is at the current position
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(ch) => ParseError::UnexpectedChar { pos: self.pos, ch },
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    /// Consumes everything up to `end`, returning it without `end`
+    fn until(&mut self, end: char) -> Result<&'a str, ParseError> {
+        let rest = &self.input[self.pos..];
+        let Some(len) = rest.find(end) else {
+            self.pos = self.input.len();
+            return Err(ParseError::UnexpectedEnd);
+        };
+        self.pos += len + end.len_utf8();
+        Ok(&rest[..len])
+    }
+
+    /// Consumes a run of characters up to whitespace, a comment or a tag
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|ch| !(ch.is_whitespace() || ch == '{' || ch == '['))
+        {
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn expect_end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(_) => Err(self.unexpected()),
+            None => Ok(()),
+        }
+    }
+
+    fn square(&mut self, size: usize) -> Result<Loc, ParseError> {
+        let col = match self.peek() {
+            Some(ch @ 'a'..='z') => ch as usize - 'a' as usize,
+            _ => return Err(self.unexpected()),
+        };
+        self.bump();
+        let Some(rank) = self.digit() else {
+            return Err(self.unexpected());
+        };
+        if col >= size || rank == 0 || rank > size {
+            return Err(ParseError::OutOfBounds);
+        }
+        Ok(Loc {
+            row: size - rank,
+            col,
+        })
+    }
+}
+
+impl Loc {
+    /// Parses a square like `a1`, where `a1` is the bottom left corner
+    pub fn from_algebraic(square: &str, size: usize) -> Result<Loc, ParseError> {
+        let mut cursor = Cursor::new(square);
+        let loc = cursor.square(size)?;
+        cursor.expect_end()?;
+        Ok(loc)
+    }
+
+    pub fn to_algebraic(self, size: usize) -> String {
+        format!("{}{}", (b'a' + self.col as u8) as char, size - self.row)
+    }
+}
+
+impl Dir {
+    fn to_ptn(self) -> char {
+        match self {
+            Self::North => '+',
+            Self::East => '>',
+            Self::South => '-',
+            Self::West => '<',
+        }
+    }
+
+    fn from_ptn(ch: char) -> Option<Dir> {
+        Dir::ALL.into_iter().find(|dir| dir.to_ptn() == ch)
+    }
+}
+
+impl StoneType {
+    fn to_ptn(self) -> &'static str {
+        match self {
+            Self::Flat => "",
+            Self::Standing => "S",
+            Self::Capstone => "C",
+        }
+    }
+}
+
+impl Turn {
+    /// Parses a PTN move such as `a1`, `Sb2` or `3c3>12`, played by `player` on
+    /// a board of size `size`. Whether the move is legal isn't checked. Tak
+    /// marks (`'`) and evaluations (`!`, `?`) after the move are ignored.
+    pub fn from_ptn(ptn: &str, player: Player, size: usize) -> Result<Turn, ParseError> {
+        Self::from_ptn_with(ptn, player, size, PtnStyle::Lenient)
+    }
+
+    /// Like `from_ptn`, but a `PtnStyle::Strict` move with a redundant count
+    /// or drop is an error at its first digit
+    pub fn from_ptn_with(
+        ptn: &str,
+        player: Player,
+        size: usize,
+        style: PtnStyle,
+    ) -> Result<Turn, ParseError> {
+        let mut cursor = Cursor::new(ptn);
+        let count = cursor.digit();
+        if style == PtnStyle::Strict && count == Some(1) {
+            return Err(ParseError::UnexpectedChar { pos: 0, ch: '1' });
+        }
+        let mut typ = None;
+        if count.is_none() {
+            typ = match cursor.peek() {
+                Some('F') => Some(StoneType::Flat),
+                Some('S') => Some(StoneType::Standing),
+                Some('C') => Some(StoneType::Capstone),
+                _ => None,
+            };
+            if typ.is_some() {
+                cursor.bump();
+            }
+        }
+        let loc = cursor.square(size)?;
+        let dir = cursor.peek().and_then(Dir::from_ptn);
+        let turn = match dir {
+            None => {
+                // A count must be followed by a direction
+                if count.is_some() {
+                    return Err(cursor.unexpected());
+                }
+                Turn::Place {
+                    loc,
+                    player,
+                    typ: typ.unwrap_or(StoneType::Flat),
+                }
+            }
+            Some(dir) => {
+                // Only placements name a stone type
+                if typ.is_some() {
+                    return Err(cursor.unexpected());
+                }
+                cursor.bump();
+                let total = count.unwrap_or(1);
+                let drops_pos = cursor.pos;
+                let mut drops = Vec::new();
+                while let Some(drop) = cursor.digit() {
+                    drops.push(drop);
+                }
+                if style == PtnStyle::Strict && drops == [total] {
+                    cursor.pos = drops_pos;
+                    return Err(cursor.unexpected());
+                }
+                if drops.is_empty() {
+                    drops.push(total);
+                }
+                if drops.contains(&0) || drops.iter().sum::<usize>() != total {
+                    return Err(ParseError::BadDrops);
+                }
+                // A capstone crushing a wall may be marked
+                if cursor.peek() == Some('*') {
+                    cursor.bump();
+                }
+                Turn::Move {
+                    loc,
+                    player,
+                    dir,
+                    total,
+                    drops,
+                }
+            }
+        };
+        while matches!(cursor.peek(), Some('\'' | '"' | '!' | '?')) {
+            cursor.bump();
+        }
+        cursor.expect_end()?;
+        Ok(turn)
+    }
+
+    pub fn to_ptn(&self, size: usize) -> String {
+        match self {
+            Self::Place {
+                loc,
+                player: _,
+                typ,
+            } => format!("{}{}", typ.to_ptn(), loc.to_algebraic(size)),
+            Self::Move {
+                loc,
+                player: _,
+                dir,
+                total,
+                drops,
+            } => {
+                let mut ptn = String::new();
+                if *total != 1 {
+                    ptn += &total.to_string();
+                }
+                ptn += &loc.to_algebraic(size);
+                ptn.push(dir.to_ptn());
+                if drops.len() > 1 {
+                    ptn.extend(drops.iter().map(|drop| drop.to_string()));
+                }
+                ptn
+            }
+        }
+    }
+}
+
+/// A move from a PTN move list, with the comments written after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedMove {
+    /// Including any marks and evaluations, which `Turn::from_ptn` ignores
+    pub ptn: String,
+    pub comments: Vec<String>,
+}
+
+impl AnnotatedMove {
+    /// Splits the moves of a PTN file, e.g. `1. a1 e5 {fast} 2. Cc3! --`,
+    /// into the moves themselves. Tag pairs, move numbers, `--` for a skipped
+    /// ply and the result are left out, as are comments before the first move.
+    pub fn parse_list(text: &str) -> Result<Vec<AnnotatedMove>, ParseError> {
+        Self::parse_game(text).map(|(_, moves)| moves)
+    }
+
+    /// Like `parse_list`, but also says who plays the first move: Black if
+    /// the list starts with `--` in place of White's
+    fn parse_game(text: &str) -> Result<(Player, Vec<AnnotatedMove>), ParseError> {
+        const RESULTS: [&str; 8] = ["R-0", "0-R", "F-0", "0-F", "1-0", "0-1", "1/2-1/2", "0-0"];
+        let mut cursor = Cursor::new(text);
+        let mut first_player = Player::White;
+        let mut moves: Vec<AnnotatedMove> = Vec::new();
+        while let Some(ch) = cursor.peek() {
+            if ch.is_whitespace() {
+                cursor.bump();
+            } else if ch == '{' {
+                cursor.bump();
+                let comment = cursor.until('}')?.trim().to_owned();
+                if let Some(last) = moves.last_mut() {
+                    last.comments.push(comment);
+                }
+            } else if ch == '[' {
+                cursor.bump();
+                cursor.until(']')?;
+            } else {
+                let word = cursor.word();
+                let move_number = word
+                    .strip_suffix('.')
+                    .is_some_and(|number| number.chars().all(|ch| ch.is_ascii_digit()));
+                if word == "--" && moves.is_empty() {
+                    first_player = Player::Black;
+                }
+                if !(move_number || word == "--" || RESULTS.contains(&word)) {
+                    moves.push(AnnotatedMove {
+                        ptn: word.to_owned(),
+                        comments: Vec::new(),
+                    });
+                }
+            }
+        }
+        Ok((first_player, moves))
+    }
+}
+
+/// Byte offset of `part` within `whole`, which it must be a slice of
+fn offset(whole: &str, part: &str) -> usize {
+    part.as_ptr() as usize - whole.as_ptr() as usize
+}
+
+/// Parses a TPS square, which is either some number of empty squares or a
+/// single stack, listed bottom to top. `room` is how many squares are left in
+/// the row, more empty squares than that are a `WrongRowWidth` of
+/// `row_width`'s row.
+fn parse_tps_square(
+    token: &str,
+    room: usize,
+    row_width: impl Fn(usize) -> ParseError,
+) -> Result<Vec<Vec<Stone>>, ParseError> {
+    let bad_token = || ParseError::BadStackToken(token.to_owned());
+    if let Some(count) = token.strip_prefix('x') {
+        let count = if count.is_empty() {
+            1
+        } else {
+            count.parse().map_err(|_| bad_token())?
+        };
+        // Checked before making them, a huge count would run out of memory
+        if count > room {
+            return Err(row_width(count));
+        }
+        return Ok(vec![Vec::new(); count]);
+    }
+    let (owners, typ) = match token.strip_suffix('S') {
+        Some(owners) => (owners, StoneType::Standing),
+        None => match token.strip_suffix('C') {
+            Some(owners) => (owners, StoneType::Capstone),
+            None => (token, StoneType::Flat),
+        },
+    };
+    let mut stack = owners
+        .chars()
+        .map(|ch| match ch {
+            '1' => Ok(Stone {
+                owner: Player::White,
+                typ: StoneType::Flat,
+            }),
+            '2' => Ok(Stone {
+                owner: Player::Black,
+                typ: StoneType::Flat,
+            }),
+            _ => Err(bad_token()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    stack.last_mut().ok_or_else(bad_token)?.typ = typ;
+    Ok(vec![stack])
+}
+
+impl GameState {
+    /// Replays PTN `moves` from the start of a game, the same way as
+    /// `from_moves`. A move that can't be played is reported with its ply, so
+    /// it can be found in the game record.
+    pub fn from_ptn_moves(size: usize, komi: i32, moves: &[&str]) -> Result<GameState, ParseError> {
+        Reserve::starting(size).ok_or(ParseError::BadSize(size))?;
+        Self::with_komi(size, komi)
+            .replay_ptn(moves)
+            .map_err(|err| ParseError::BadMove(Box::new(err)))
+    }
+
+    /// Replays a PTN move list such as `to_ptn_game` writes, e.g. `1. a1 e5
+    /// 2. c3`, like `from_ptn_moves`. A list that starts with `1. --` is a game
+    /// Black started.
+    pub fn from_ptn_list(size: usize, komi: i32, text: &str) -> Result<GameState, ParseError> {
+        let (first_player, moves) = AnnotatedMove::parse_game(text)?;
+        let ptns: Vec<&str> = moves.iter().map(|played| played.ptn.as_str()).collect();
+        Reserve::starting(size).ok_or(ParseError::BadSize(size))?;
+        Self::with_komi(size, komi)
+            .with_first_player(first_player)
+            .replay_ptn(&ptns)
+            .map_err(|err| ParseError::BadMove(Box::new(err)))
+    }
+
+    /// Plays PTN `moves` on from this position, stopping at the first one
+    /// that can't be read or played
+    pub(super) fn replay_ptn(mut self, moves: &[&str]) -> Result<GameState, ReplayError> {
+        for (ply, ptn) in moves.iter().enumerate() {
+            self.apply_ptn(ptn).map_err(|cause| ReplayError {
+                ply,
+                ptn: (*ptn).to_owned(),
+                cause,
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// Reads one PTN move for the player to move and plays it, returning how
+    /// the game stands after it. Nothing changes if it can't be read or played.
+    pub fn apply_ptn(&mut self, ptn: &str) -> Result<GameResult, ApplyPtnError> {
+        let turn = Turn::from_ptn(ptn, self.current_player, self.board.size())
+            .map_err(ApplyPtnError::Parse)?;
+        self.try_apply_turn(&turn).map_err(ApplyPtnError::Illegal)?;
+        Ok(self.result)
+    }
+
+    /// Loads a position from TPS, e.g. `x3,12,2S/x5/x5/x5/x5 1 8`. Reserves are
+    /// whatever isn't on the board, and there is no history to undo.
+    pub fn from_tps(tps: &str) -> Result<GameState, ParseError> {
+        let mut fields = tps.split(' ');
+        let board_field = fields.next().unwrap_or_default();
+        let rows: Vec<&str> = board_field.split('/').collect();
+        let size = rows.len();
+        Reserve::starting(size).ok_or(ParseError::BadSize(size))?;
+
+        let mut board = Board::new(size);
+        for (row, squares) in rows.iter().enumerate() {
+            let mut stacks = Vec::new();
+            for token in squares.split(',') {
+                let filled = stacks.len();
+                let row_width = |count: usize| ParseError::WrongRowWidth {
+                    row,
+                    width: filled.saturating_add(count),
+                    size,
+                };
+                stacks.extend(parse_tps_square(
+                    token,
+                    size.saturating_sub(filled),
+                    row_width,
+                )?);
+            }
+            if stacks.len() != size {
+                return Err(ParseError::WrongRowWidth {
+                    row,
+                    width: stacks.len(),
+                    size,
+                });
+            }
+            for (col, stack) in stacks.into_iter().enumerate() {
+                board.set_stack(Loc { row, col }, stack);
+            }
+        }
+
+        let player_field = fields.next().ok_or(ParseError::UnexpectedEnd)?;
+        let current_player = match player_field {
+            "1" => Player::White,
+            "2" => Player::Black,
+            _ => {
+                return Err(match player_field.chars().next() {
+                    Some(ch) => ParseError::UnexpectedChar {
+                        pos: offset(tps, player_field),
+                        ch,
+                    },
+                    None => ParseError::UnexpectedEnd,
+                });
+            }
+        };
+
+        let move_field = fields.next().ok_or(ParseError::UnexpectedEnd)?;
+        let move_number = move_field
+            .parse::<usize>()
+            .ok()
+            .filter(|number| *number > 0)
+            .ok_or_else(|| ParseError::BadMoveNumber(move_field.to_owned()))?;
+        if let Some(extra) = fields.next() {
+            // Points at the space before the extra field
+            return Err(ParseError::UnexpectedChar {
+                pos: offset(tps, extra) - 1,
+                ch: ' ',
+            });
+        }
+
+        let ply = (move_number - 1)
+            .checked_mul(2)
+            .and_then(|ply| ply.checked_add(usize::from(current_player == Player::Black)))
+            .ok_or_else(|| ParseError::BadMoveNumber(move_field.to_owned()))?;
+        Self::from_position(board, current_player, ply)
+    }
+
+    /// A game at `ply`, as TPS counts it, with `board` on it and
+    /// `current_player` to move, with whatever isn't on the board left in
+    /// reserve. Each turn of the opening places a stone, so a position in it
+    /// with a stone fewer than its ply is from a game Black started.
+    pub(super) fn from_position(
+        board: Board,
+        current_player: Player,
+        ply: usize,
+    ) -> Result<GameState, ParseError> {
+        let size = board.size();
+        let starting = Reserve::starting(size).ok_or(ParseError::BadSize(size))?;
+        let mut reserves = Reserves::new(starting);
+        for player in Player::all() {
+            let reserve = &mut reserves[player];
+            for stone in board.stacks.iter().flatten() {
+                if stone.owner != player {
+                    continue;
+                }
+                let left = match stone.typ {
+                    StoneType::Flat | StoneType::Standing => &mut reserve.reg,
+                    StoneType::Capstone => &mut reserve.cap,
+                };
+                *left = left
+                    .checked_sub(1)
+                    .ok_or(ParseError::TooManyStones(player))?;
+            }
+        }
+
+        let mut state = GameState::new(size);
+        state.board = board;
+        state.roads = Roads::build(&state.board);
+        state.reserves = reserves;
+        let black_first = ply <= 2 && state.board.all_stones().count() + 1 == ply;
+        if black_first {
+            state.first_player = Player::Black;
+        }
+        state.current_player = current_player;
+        state.ply = ply - usize::from(black_first);
+        state.result = state.compute_result(current_player.next());
+        state.end_if_stuck();
+        Ok(state)
+    }
+
+    pub fn to_tps(&self) -> String {
+        let size = self.board.size();
+        let rows: Vec<String> = (0..size)
+            .map(|row| {
+                let mut squares = Vec::new();
+                let mut empty = 0;
+                for col in 0..size {
+                    let stack = &self.board[Loc { row, col }];
+                    if stack.is_empty() {
+                        empty += 1;
+                        continue;
+                    }
+                    if empty > 0 {
+                        squares.push(empty_token(empty));
+                        empty = 0;
+                    }
+                    squares.push(stack.iter().map(|stone| stone.to_string()).collect());
+                }
+                if empty > 0 {
+                    squares.push(empty_token(empty));
+                }
+                squares.join(",")
+            })
+            .collect();
+        format!(
+            "{} {} {}",
+            rows.join("/"),
+            self.current_player,
+            self.move_number()
+        )
+    }
+
+    /// The game as a complete PTN file, see `to_ptn_game`
+    pub fn to_ptn(&self) -> String {
+        self.to_ptn_game(&[])
+    }
+
+    /// The last turn played in PTN, with `*` if it crushed a wall and the
+    /// result after it if it ended the game, e.g. `a3 R-0`
+    pub fn last_move_ptn(&self) -> Option<String> {
+        let played = self.history.last()?;
+        let mut ptn = played.to_ptn(self.board.size());
+        if let Some(result) = played.result.to_ptn_result() {
+            ptn.push(' ');
+            ptn.push_str(result);
+        }
+        Some(ptn)
+    }
+
+    /// The game so far as a PTN file: `Size` and `Komi` tags, then `tags`,
+    /// then the moves. Games that didn't start from an empty board get a
+    /// `TPS` tag with the position before the first move, and finished games
+    /// a `Result` tag and the result after the last move.
+    pub fn to_ptn_game(&self, tags: &[(&str, &str)]) -> String {
+        let size = self.board.size();
+        let mut start = self.clone();
+        while start.undo() {}
+
+        let mut ptn = format!(
+            "[Size \"{size}\"]\n[Komi \"{}\"]\n",
+            f64::from(self.komi) / 2.
+        );
+        for (name, value) in tags {
+            ptn.push_str(&format!("[{name} \"{value}\"]\n"));
+        }
+        if !(start.is_new_game()) {
+            ptn.push_str(&format!("[TPS \"{}\"]\n", start.to_tps()));
+        }
+        let result = self.result.to_ptn_result();
+        if let Some(result) = result {
+            ptn.push_str(&format!("[Result \"{result}\"]\n"));
+        }
+
+        let mut lines = Vec::new();
+        for (ply, played) in (start.notation_ply()..).zip(&self.history) {
+            if ply % 2 == 0 || lines.is_empty() {
+                let skipped = if ply % 2 == 0 { "" } else { " --" };
+                lines.push(format!("{}.{skipped}", ply / 2 + 1));
+            }
+            let line = lines.last_mut().unwrap();
+            line.push(' ');
+            line.push_str(&played.to_ptn(size));
+        }
+        if let Some(result) = result {
+            match lines.last_mut() {
+                Some(line) => {
+                    line.push(' ');
+                    line.push_str(result);
+                }
+                None => lines.push(result.to_owned()),
+            }
+        }
+        ptn.push('\n');
+        for line in lines {
+            ptn.push_str(&line);
+            ptn.push('\n');
+        }
+        ptn
+    }
+}
+
+impl MoveRecord {
+    /// The turn in PTN, marked with `*` if it crushed a wall
+    pub fn to_ptn(&self, size: usize) -> String {
+        let mut ptn = self.turn.to_ptn(size);
+        if self.crushed.is_some() {
+            ptn.push('*');
+        }
+        ptn
+    }
+}
+
+impl GameResult {
+    /// The result as written in PTN, e.g. `R-0` for a road win by White, or
+    /// `None` if the game isn't over. PTN has no token for losing on time or
+    /// by resigning, so those are written as plain wins.
+    pub fn to_ptn_result(self) -> Option<&'static str> {
+        Some(match self {
+            Self::Ongoing => return None,
+            Self::RoadWin(Player::White) => "R-0",
+            Self::RoadWin(Player::Black) => "0-R",
+            Self::FlatWin(Player::White) => "F-0",
+            Self::FlatWin(Player::Black) => "0-F",
+            Self::TimeoutWin(Player::White) | Self::ResignationWin(Player::White) => "1-0",
+            Self::TimeoutWin(Player::Black) | Self::ResignationWin(Player::Black) => "0-1",
+            Self::Draw => "1/2-1/2",
+        })
+    }
+}
+
+fn empty_token(count: usize) -> String {
+    if count == 1 {
+        "x".to_owned()
+    } else {
+        format!("x{count}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algebraic() {
+        assert_eq!(Loc::from_algebraic("a1", 5), Ok(Loc { row: 4, col: 0 }));
+        assert_eq!(Loc::from_algebraic("e5", 5), Ok(Loc { row: 0, col: 4 }));
+        assert_eq!(Loc { row: 1, col: 2 }.to_algebraic(6), "c5");
+        assert_eq!(Loc::from_algebraic("a6", 5), Err(ParseError::OutOfBounds));
+        assert_eq!(Loc::from_algebraic("f1", 5), Err(ParseError::OutOfBounds));
+        assert_eq!(
+            Loc::from_algebraic("A1", 5),
+            Err(ParseError::UnexpectedChar { pos: 0, ch: 'A' })
+        );
+        assert_eq!(Loc::from_algebraic("a", 5), Err(ParseError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn ptn_moves() {
+        for ptn in ["a1", "Sb2", "Cc3", "c3>", "3c3>12", "5a1+221"] {
+            let turn = Turn::from_ptn(ptn, Player::White, 8).unwrap();
+            assert_eq!(turn.to_ptn(8), ptn);
+        }
+        assert_eq!(
+            Turn::from_ptn("2d4<11*", Player::Black, 5),
+            Ok(Turn::Move {
+                loc: Loc { row: 1, col: 3 },
+                player: Player::Black,
+                dir: Dir::West,
+                total: 2,
+                drops: vec![1, 1],
+            })
+        );
+        assert_eq!(
+            Turn::from_ptn("Fa1", Player::White, 5).unwrap().to_ptn(5),
+            "a1"
+        );
+    }
+
+    #[test]
+    fn ptn_with_comments_and_annotations() {
+        let text = "[Size \"5\"]\n{Before the game} 1. a1 e5 {quick reply}\n\
+                    2. Cc3! Sd4?? {a blunder}{really}\n3. c3-' 1-0";
+        let moves = AnnotatedMove::parse_list(text).unwrap();
+        let ptns: Vec<&str> = moves.iter().map(|played| played.ptn.as_str()).collect();
+        assert_eq!(ptns, ["a1", "e5", "Cc3!", "Sd4??", "c3-'"]);
+        assert!(moves[0].comments.is_empty());
+        assert_eq!(moves[1].comments, ["quick reply"]);
+        assert_eq!(moves[3].comments, ["a blunder", "really"]);
+        let state = GameState::from_ptn_moves(5, 0, &ptns).unwrap();
+        assert_eq!(state.ply(), 5);
+        assert_eq!(
+            Turn::from_ptn("2d4<11*!?", Player::Black, 5),
+            Turn::from_ptn("2d4<11", Player::Black, 5)
+        );
+
+        assert_eq!(
+            AnnotatedMove::parse_list("1. a1 {unfinished"),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn ptn_errors() {
+        assert_eq!(
+            Turn::from_ptn("a1^", Player::White, 5),
+            Err(ParseError::UnexpectedChar { pos: 2, ch: '^' })
+        );
+        assert_eq!(
+            Turn::from_ptn("Sa1>", Player::White, 5),
+            Err(ParseError::UnexpectedChar { pos: 3, ch: '>' })
+        );
+        assert_eq!(
+            Turn::from_ptn("3a1", Player::White, 5),
+            Err(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            Turn::from_ptn("3a1>22", Player::White, 5),
+            Err(ParseError::BadDrops)
+        );
+        assert_eq!(
+            Turn::from_ptn("2a1>20", Player::White, 5),
+            Err(ParseError::BadDrops)
+        );
+        assert_eq!(
+            Turn::from_ptn("h8", Player::White, 5),
+            Err(ParseError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn strict_ptn_rejects_implied_counts_and_drops() {
+        let parse = |ptn, style| Turn::from_ptn_with(ptn, Player::White, 5, style);
+        for ptn in ["a1>", "1a1>", "a1>1", "3c3>3"] {
+            assert!(parse(ptn, PtnStyle::Lenient).is_ok(), "{ptn}");
+        }
+        assert_eq!(
+            parse("a1>", PtnStyle::Strict),
+            parse("1a1>1", PtnStyle::Lenient)
+        );
+        for ptn in ["a1", "3c3>", "3c3>12", "Sb2"] {
+            assert!(parse(ptn, PtnStyle::Strict).is_ok(), "{ptn}");
+        }
+        assert_eq!(
+            parse("1a1>", PtnStyle::Strict),
+            Err(ParseError::UnexpectedChar { pos: 0, ch: '1' })
+        );
+        assert_eq!(
+            parse("a1>1", PtnStyle::Strict),
+            Err(ParseError::UnexpectedChar { pos: 3, ch: '1' })
+        );
+        assert_eq!(
+            parse("3c3>3", PtnStyle::Strict),
+            Err(ParseError::UnexpectedChar { pos: 4, ch: '3' })
+        );
+    }
+
+    #[test]
+    fn ptn_replay_reports_illegal_move() {
+        let moves = ["a1", "e5", "b1", "e4", "a1", "e3", "Sc1", "b1<"];
+        let err = GameState::from_ptn_moves(5, 0, &moves).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::BadMove(Box::new(ReplayError {
+                ply: 4,
+                ptn: "a1".to_owned(),
+                cause: ApplyPtnError::Illegal(TurnError::IllegalOnBoard),
+            }))
+        );
+        assert_eq!(
+            err.to_string(),
+            "'a1' (ply 5) is not a legal move: the board doesn't allow it"
+        );
+    }
+
+    #[test]
+    fn ptn_replay_reports_unreadable_move() {
+        let err = GameState::from_ptn_moves(5, 0, &["a1", "e5", "zz"]).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::BadMove(Box::new(ReplayError {
+                ply: 2,
+                ptn: "zz".to_owned(),
+                cause: ApplyPtnError::Parse(ParseError::UnexpectedChar { pos: 1, ch: 'z' }),
+            }))
+        );
+        assert_eq!(
+            err.to_string(),
+            "'zz' (ply 3) can't be read: unexpected 'z' at position 1"
+        );
+    }
+
+    #[test]
+    fn apply_ptn_plays_for_the_player_to_move() {
+        let mut state = GameState::new(5);
+        for ptn in ["a1", "e5", "Cc3", "Sd3"] {
+            assert_eq!(state.apply_ptn(ptn), Ok(GameResult::Ongoing));
+        }
+        assert_eq!(
+            state.apply_ptn("c3"),
+            Err(ApplyPtnError::Illegal(TurnError::IllegalOnBoard))
+        );
+        assert_eq!(
+            state.apply_ptn("c9"),
+            Err(ApplyPtnError::Parse(ParseError::OutOfBounds))
+        );
+        assert_eq!(state.apply_ptn("c3>"), Ok(GameResult::Ongoing));
+        // a1 and e5 are swapped
+        assert_eq!(state.to_tps(), "x4,1/x5/x3,21C,x/x5/2,x4 2 3");
+    }
+
+    #[test]
+    fn last_move_ptn_marks_crushes_and_results() {
+        assert_eq!(GameState::new(5).last_move_ptn(), None);
+        let state = GameState::from_ptn_moves(5, 0, &["a1", "e5", "Cc3", "Sd3", "c3>"]).unwrap();
+        assert_eq!(state.last_move_ptn().as_deref(), Some("c3>*"));
+        let state = GameState::from_ptn_moves(3, 0, &["c3", "a1", "a2", "c2", "a3"]).unwrap();
+        assert_eq!(state.last_move_ptn().as_deref(), Some("a3 R-0"));
+    }
+
+    #[test]
+    fn ptn_game_export() {
+        let state = GameState::from_ptn_moves(5, 3, &["a1", "e5", "Cc3"]).unwrap();
+        assert_eq!(
+            state.to_ptn_game(&[("Player1", "Ann"), ("Player2", "Ben")]),
+            "\
+[Size \"5\"]
+[Komi \"1.5\"]
+[Player1 \"Ann\"]
+[Player2 \"Ben\"]
+
+1. a1 e5
+2. Cc3
+"
+        );
+
+        let mut state = GameState::from_tps("x5/x5/x2,1,x2/x5/x5 2 3").unwrap();
+        assert!(state.apply_turn(&Turn::from_ptn("c4", Player::Black, 5).unwrap()));
+        assert!(state.apply_turn(&Turn::from_ptn("d3", Player::White, 5).unwrap()));
+        assert_eq!(
+            state.to_ptn_game(&[]),
+            "\
+[Size \"5\"]
+[Komi \"0\"]
+[TPS \"x5/x5/x2,1,x2/x5/x5 2 3\"]
+
+3. -- c4
+4. d3
+"
+        );
+    }
+
+    #[test]
+    fn ptn_export_round_trip() {
+        let moves = [
+            "e5", "a1", "b1", "e4", "c2", "e3", "c2-", "e2", "d1", "Cd2", "e1",
+        ];
+        let state = GameState::from_ptn_moves(5, 0, &moves[..10]).unwrap();
+        let mut won = state.clone();
+        assert!(won.apply_turn(&Turn::from_ptn("e1", Player::White, 5).unwrap()));
+        assert_eq!(won.result(), GameResult::RoadWin(Player::White));
+
+        for game in [state, won] {
+            let ptn = game.to_ptn();
+            let result = game.result().to_ptn_result();
+            assert_eq!(
+                ptn.contains("[Result \"R-0\"]\n"),
+                result.is_some(),
+                "{ptn}"
+            );
+            let tokens: Vec<&str> = ptn
+                .lines()
+                .filter(|line| !line.starts_with('['))
+                .flat_map(str::split_whitespace)
+                .filter(|token| !token.ends_with('.') && Some(*token) != result)
+                .collect();
+            let replayed = GameState::from_ptn_moves(5, 0, &tokens).unwrap();
+            assert!(replayed == game, "{ptn}");
+        }
+        assert!(
+            GameState::from_ptn_moves(5, 0, &moves)
+                .unwrap()
+                .to_ptn()
+                .ends_with("6. e1 R-0\n")
+        );
+        assert_eq!(GameResult::Draw.to_ptn_result(), Some("1/2-1/2"));
+    }
+
+    #[test]
+    fn ptn_export_marks_crushes() {
+        let state = GameState::from_ptn_moves(5, 0, &["a1", "e5", "Cc3", "Sd3", "c3>"]).unwrap();
+        let crush = state.history().last().unwrap();
+        assert_eq!(crush.crushed, Some(Loc { row: 2, col: 3 }));
+        assert_eq!(crush.result, GameResult::Ongoing);
+        assert!(
+            state.history()[..4]
+                .iter()
+                .all(|played| played.crushed.is_none())
+        );
+        let ptn = state.to_ptn();
+        assert!(ptn.ends_with("\n1. a1 e5\n2. Cc3 Sd3\n3. c3>*\n"), "{ptn}");
+    }
+
+    #[test]
+    fn ptn_replay_enforces_flat_opening() {
+        let state = GameState::from_ptn_moves(5, 0, &["a1", "e5", "Cc3", "Sd3"]).unwrap();
+        assert_eq!(state.board().stack_height(Loc { row: 2, col: 2 }), 1);
+        assert_eq!(
+            GameState::from_ptn_moves(5, 0, &["Ca1"]).unwrap_err(),
+            ParseError::BadMove(Box::new(ReplayError {
+                ply: 0,
+                ptn: "Ca1".to_owned(),
+                cause: ApplyPtnError::Illegal(TurnError::OpeningNotFlat),
+            }))
+        );
+        assert_eq!(
+            GameState::from_ptn_moves(5, 0, &["a1", "Se5"]).unwrap_err(),
+            ParseError::BadMove(Box::new(ReplayError {
+                ply: 1,
+                ptn: "Se5".to_owned(),
+                cause: ApplyPtnError::Illegal(TurnError::OpeningNotFlat),
+            }))
+        );
+        assert_eq!(
+            GameState::from_ptn_moves(5, 0, &["e5", "a1", "a1+"])
+                .map(|state| state.history().len()),
+            Ok(3)
+        );
+        assert_eq!(
+            GameState::from_ptn_moves(9, 0, &[]).unwrap_err(),
+            ParseError::BadSize(9)
+        );
+    }
+
+    #[test]
+    fn tps_round_trip() {
+        let tps =
+            "x3,12,2S/x,22S,22C,11,21/121,212,12,1121C,1212S/21S,1,21,211S,12S/x,21S,2,x2 1 26";
+        let state = GameState::from_tps(tps).unwrap();
+        assert_eq!(state.to_tps(), tps);
+        assert_eq!(state.current_player(), Player::White);
+        assert_eq!(state.result(), GameResult::Ongoing);
+        assert_eq!(GameState::new(6).to_tps(), "x6/x6/x6/x6/x6/x6 1 1");
+    }
+
+    #[test]
+    fn tps_errors() {
+        assert_eq!(
+            GameState::from_tps("x5/x4/x5/x5/x5 1 1").unwrap_err(),
+            ParseError::WrongRowWidth {
+                row: 1,
+                width: 4,
+                size: 5
+            }
+        );
+        // Too many empty squares is caught before they are made
+        assert_eq!(
+            GameState::from_tps("x99999999999/x3/x3 1 1").unwrap_err(),
+            ParseError::WrongRowWidth {
+                row: 0,
+                width: 99999999999,
+                size: 3
+            }
+        );
+        assert_eq!(
+            GameState::from_tps("x2,x2/x3/x3 1 1").unwrap_err(),
+            ParseError::WrongRowWidth {
+                row: 0,
+                width: 4,
+                size: 3
+            }
+        );
+        let huge = format!("x3/x3/x3 1 {}", usize::MAX);
+        assert_eq!(
+            GameState::from_tps(&huge).unwrap_err(),
+            ParseError::BadMoveNumber(usize::MAX.to_string())
+        );
+        assert_eq!(
+            GameState::from_tps("x5/x5/x2,13,x2/x5/x5 1 1").unwrap_err(),
+            ParseError::BadStackToken("13".to_owned())
+        );
+        assert_eq!(
+            GameState::from_tps("x5/x5/x2,S,x2/x5/x5 1 1").unwrap_err(),
+            ParseError::BadStackToken("S".to_owned())
+        );
+        assert_eq!(
+            GameState::from_tps("x2/x2 1 1").unwrap_err(),
+            ParseError::BadSize(2)
+        );
+        assert_eq!(
+            GameState::from_tps("x3/x3/x3 3 1").unwrap_err(),
+            ParseError::UnexpectedChar { pos: 9, ch: '3' }
+        );
+        assert_eq!(
+            GameState::from_tps("x3/x3/x3 1").unwrap_err(),
+            ParseError::UnexpectedEnd
+        );
+        assert_eq!(
+            GameState::from_tps("x3/x3/x3 1 0").unwrap_err(),
+            ParseError::BadMoveNumber("0".to_owned())
+        );
+        assert_eq!(
+            GameState::from_tps("1C,x2/x3/x3 2 1").unwrap_err(),
+            ParseError::TooManyStones(Player::White)
+        );
+    }
+}
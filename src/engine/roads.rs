@@ -0,0 +1,219 @@
+//! Road connectivity kept up to date turn by turn, so checking for a road
+//! doesn't need a flood fill of the whole board.
+//!
+//! Each player has a disjoint-set forest over the squares, where the road
+//! pieces they own are joined to their road piece neighbours. Every group
+//! remembers which edges of the board it touches, and a road is a group that
+//! touches two opposite edges. Turns that only add road pieces are merged in
+//! as they come, turns that take one away rebuild that player's forest.
+
+use super::{Board, Dir, Loc, Player};
+
+const NORTH: u8 = 1;
+const EAST: u8 = 2;
+const SOUTH: u8 = 4;
+const WEST: u8 = 8;
+
+#[derive(Debug, Clone)]
+struct RoadSets {
+    parent: Vec<usize>,
+    /// For the root of each group, the edges any of its squares are on
+    edges: Vec<u8>,
+    road: bool,
+}
+
+impl RoadSets {
+    fn build(board: &Board, player: Player) -> Self {
+        let size = board.size();
+        let mut sets = RoadSets {
+            parent: (0..size * size).collect(),
+            edges: vec![0; size * size],
+            road: false,
+        };
+        for index in 0..size * size {
+            let loc = Loc::from_index(index, size);
+            if board.is_road_piece(loc, player) {
+                sets.add(board, loc, player);
+            }
+        }
+        sets
+    }
+
+    /// The root of the group `index` is in, halving the path there on the way
+    fn find(&mut self, mut index: usize) -> usize {
+        while self.parent[index] != index {
+            self.parent[index] = self.parent[self.parent[index]];
+            index = self.parent[index];
+        }
+        index
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        self.parent[b] = a;
+        self.edges[a] |= self.edges[b];
+        let edges = self.edges[a];
+        self.road |=
+            edges & (NORTH | SOUTH) == NORTH | SOUTH || edges & (EAST | WEST) == EAST | WEST;
+    }
+
+    /// Starts a group for the new road piece at `loc`, and joins it to the
+    /// groups of its neighbours
+    fn add(&mut self, board: &Board, loc: Loc, player: Player) {
+        let size = board.size();
+        let index = loc.to_index(size);
+        self.parent[index] = index;
+        self.edges[index] = edge_mask(loc, size);
+        // A single square only spans the board when the board is one square
+        // wide, which can't happen, so a road always comes from a union
+        for dir in Dir::ALL {
+            if let Some(next) = board.neighbor(loc, dir)
+                && board.is_road_piece(next, player)
+            {
+                self.union(index, next.to_index(size));
+            }
+        }
+    }
+
+    /// The root every square is grouped under, with groups numbered by their
+    /// first square, so that forests can be compared however they were built
+    fn canonical(&self) -> Vec<usize> {
+        let root = |mut index: usize| {
+            while self.parent[index] != index {
+                index = self.parent[index];
+            }
+            index
+        };
+        let mut first = vec![usize::MAX; self.parent.len()];
+        (0..self.parent.len())
+            .map(|index| {
+                let root = root(index);
+                if first[root] == usize::MAX {
+                    first[root] = index;
+                }
+                first[root]
+            })
+            .collect()
+    }
+}
+
+fn edge_mask(loc: Loc, size: usize) -> u8 {
+    let mut mask = 0;
+    if loc.row == 0 {
+        mask |= NORTH;
+    }
+    if loc.row == size - 1 {
+        mask |= SOUTH;
+    }
+    if loc.col == 0 {
+        mask |= WEST;
+    }
+    if loc.col == size - 1 {
+        mask |= EAST;
+    }
+    mask
+}
+
+/// Both players' road groups, which `GameState` updates whenever the board
+/// changes
+#[derive(Debug, Clone)]
+pub(super) struct Roads {
+    white: RoadSets,
+    black: RoadSets,
+}
+
+/// Which players had a road piece on a square before a turn changed it
+pub(super) type RoadPieces = Vec<(Loc, [bool; 2])>;
+
+impl Roads {
+    pub(super) fn build(board: &Board) -> Self {
+        Roads {
+            white: RoadSets::build(board, Player::White),
+            black: RoadSets::build(board, Player::Black),
+        }
+    }
+
+    fn sets(&mut self, player: Player) -> &mut RoadSets {
+        match player {
+            Player::White => &mut self.white,
+            Player::Black => &mut self.black,
+        }
+    }
+
+    pub(super) fn has_road(&self, player: Player) -> bool {
+        match player {
+            Player::White => self.white.road,
+            Player::Black => self.black.road,
+        }
+    }
+
+    /// What `update` needs to know about `squares` before they change
+    pub(super) fn before(board: &Board, squares: &[Loc]) -> RoadPieces {
+        squares
+            .iter()
+            .map(|&loc| {
+                let pieces = Player::all().map(|p| board.is_road_piece(loc, p));
+                (loc, pieces)
+            })
+            .collect()
+    }
+
+    /// Catches up with `board` after the squares in `before` changed
+    pub(super) fn update(&mut self, board: &Board, before: &RoadPieces) {
+        for (i, player) in Player::all().into_iter().enumerate() {
+            let lost = before
+                .iter()
+                .any(|(loc, had)| had[i] && !board.is_road_piece(*loc, player));
+            if lost {
+                *self.sets(player) = RoadSets::build(board, player);
+                continue;
+            }
+            for (loc, had) in before {
+                if !had[i] && board.is_road_piece(*loc, player) {
+                    self.sets(player).add(board, *loc, player);
+                }
+            }
+        }
+    }
+}
+
+/// Roads are derived from the board, so two of them are equal when they
+/// group the squares the same way, whatever order they were joined in
+impl PartialEq for Roads {
+    fn eq(&self, other: &Self) -> bool {
+        self.white.canonical() == other.white.canonical()
+            && self.black.canonical() == other.black.canonical()
+            && self.white.road == other.white.road
+            && self.black.road == other.black.road
+    }
+}
+
+impl Eq for Roads {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::random_games;
+
+    #[test]
+    fn matches_flood_fill_through_random_games() {
+        let games = random_games(2, 40, |_, _, state| {
+            for player in Player::all() {
+                assert_eq!(
+                    state.roads.has_road(player),
+                    state.board().check_road(player),
+                    "player {player} in\n{state}"
+                );
+            }
+            assert!(state.roads == Roads::build(state.board()));
+        });
+        for mut state in games {
+            while state.undo() {
+                assert!(state.roads == Roads::build(state.board()));
+            }
+        }
+    }
+}
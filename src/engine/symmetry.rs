@@ -0,0 +1,174 @@
+//! The 8 ways of turning or reflecting the board onto itself. Tak's rules
+//! don't care which way up the board is, so a game played through any of them
+//! is the same game.
+
+use super::{Dir, GameState, Loc, Turn};
+
+/// Reflecting across the main diagonal if `transpose`, then flipping the rows
+/// and columns over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symmetry {
+    transpose: bool,
+    flip_rows: bool,
+    flip_cols: bool,
+}
+
+impl Symmetry {
+    pub const IDENTITY: Symmetry = Symmetry {
+        transpose: false,
+        flip_rows: false,
+        flip_cols: false,
+    };
+
+    /// All 8, starting with `IDENTITY`
+    pub fn all() -> impl Iterator<Item = Symmetry> {
+        (0..8).map(|bits| Symmetry {
+            transpose: bits & 4 != 0,
+            flip_rows: bits & 2 != 0,
+            flip_cols: bits & 1 != 0,
+        })
+    }
+
+    pub fn loc(self, loc: Loc, size: usize) -> Loc {
+        let Loc { mut row, mut col } = loc;
+        if self.transpose {
+            (row, col) = (col, row);
+        }
+        if self.flip_rows {
+            row = size - 1 - row;
+        }
+        if self.flip_cols {
+            col = size - 1 - col;
+        }
+        Loc { row, col }
+    }
+
+    pub fn dir(self, dir: Dir) -> Dir {
+        let mut dir = dir;
+        if self.transpose {
+            dir = match dir {
+                Dir::North => Dir::West,
+                Dir::West => Dir::North,
+                Dir::South => Dir::East,
+                Dir::East => Dir::South,
+            };
+        }
+        if self.flip_rows {
+            dir = match dir {
+                Dir::North => Dir::South,
+                Dir::South => Dir::North,
+                Dir::East | Dir::West => dir,
+            };
+        }
+        if self.flip_cols {
+            dir = match dir {
+                Dir::East => Dir::West,
+                Dir::West => Dir::East,
+                Dir::North | Dir::South => dir,
+            };
+        }
+        dir
+    }
+
+    pub fn turn(self, turn: &Turn, size: usize) -> Turn {
+        match turn {
+            Turn::Place { loc, player, typ } => Turn::Place {
+                loc: self.loc(*loc, size),
+                player: *player,
+                typ: *typ,
+            },
+            Turn::Move {
+                loc,
+                player,
+                dir,
+                total,
+                drops,
+            } => Turn::Move {
+                loc: self.loc(*loc, size),
+                player: *player,
+                dir: self.dir(*dir),
+                total: *total,
+                drops: drops.clone(),
+            },
+        }
+    }
+}
+
+/// Whether games `a` and `b`, both played from an empty board of `size`, are
+/// the same game under one of the symmetries: every turn of one is the other's
+/// turned or reflected the same way, ending in the same position. Games with an
+/// illegal turn aren't equivalent to anything.
+pub fn games_equivalent(a: &[Turn], b: &[Turn], size: usize) -> bool {
+    if a.len() != b.len() || GameState::try_new(size).is_err() {
+        return false;
+    }
+    let (Some(end_a), Some(end_b)) = (
+        GameState::from_moves(size, 0, a),
+        GameState::from_moves(size, 0, b),
+    ) else {
+        return false;
+    };
+    let (board_a, board_b) = (end_a.board(), end_b.board());
+    Symmetry::all().any(|symmetry| {
+        a.iter()
+            .zip(b)
+            .all(|(turn_a, turn_b)| symmetry.turn(turn_a, size) == *turn_b)
+            && board_a.all_stones().count() == board_b.all_stones().count()
+            && board_a.all_stones().all(|(loc, layer, stone)| {
+                board_b[symmetry.loc(loc, size)].get(layer) == Some(&stone)
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Player;
+
+    fn game(ptns: &[&str], size: usize) -> Vec<Turn> {
+        let mut state = GameState::new(size);
+        ptns.iter()
+            .map(|ptn| {
+                let turn = Turn::from_ptn(ptn, state.current_player(), size).unwrap();
+                assert!(state.apply_turn(&turn), "{ptn}");
+                turn
+            })
+            .collect()
+    }
+
+    #[test]
+    fn symmetries_are_all_different() {
+        let corner = Loc { row: 0, col: 1 };
+        let images: Vec<Loc> = Symmetry::all().map(|s| s.loc(corner, 5)).collect();
+        for (i, loc) in images.iter().enumerate() {
+            assert!(!images[..i].contains(loc), "{loc:?} twice");
+        }
+        for symmetry in Symmetry::all() {
+            // A step in `dir` lands where the transformed step does
+            let from = Loc { row: 2, col: 2 };
+            for dir in Dir::ALL {
+                assert_eq!(
+                    symmetry.loc(from.move_in(dir), 5),
+                    symmetry.loc(from, 5).move_in(symmetry.dir(dir))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_game_and_its_mirror_are_equivalent() {
+        let played = game(&["a1", "e5", "b1", "Sd4", "b1+", "d4<"], 5);
+        let mirrored = game(&["e1", "a5", "d1", "Sb4", "d1+", "b4>"], 5);
+        assert!(games_equivalent(&played, &mirrored, 5));
+        assert!(games_equivalent(&mirrored, &played, 5));
+        assert!(games_equivalent(&played, &played, 5));
+
+        // The same squares, but a different spread at the end
+        let different = game(&["e1", "a5", "d1", "Sb4", "d1+", "b4+"], 5);
+        assert!(!games_equivalent(&played, &different, 5));
+        assert!(!games_equivalent(&played, &played[..5], 5));
+
+        let illegal = vec![Turn::from_ptn("Sa1", Player::White, 5).unwrap()];
+        assert!(!games_equivalent(&illegal, &illegal, 5));
+    }
+}
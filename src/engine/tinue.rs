@@ -0,0 +1,120 @@
+//! Searching for tinuè: a road the player to move can force, however their
+//! opponent replies.
+//!
+//! It's a plain search of every line up to the given depth, played with
+//! `GameState::make` and `unmake`, so it gets slow quickly past 3 plies.
+
+use super::{GameResult, GameState, Player};
+
+impl GameState {
+    /// The fewest plies, up to `max_depth`, in which the player to move can
+    /// force a road win, or `None` if they can't in that many. Only wins on
+    /// their own turns count, so the depth is always odd.
+    pub fn tinue_depth(&self, max_depth: u32) -> Option<u32> {
+        if self.result.is_over() {
+            return None;
+        }
+        let attacker = self.current_player;
+        let mut state = self.clone();
+        (1..=max_depth)
+            .step_by(2)
+            .find(|&plies| state.forces_road(attacker, plies))
+    }
+
+    /// Whether `player` can't stop their opponent forcing a road within
+    /// `depth` plies of whatever `player` does next, e.g. to suggest
+    /// resigning. If it's already the opponent's turn, whether they can force
+    /// one within `depth`. Only roads count, not flat wins.
+    pub fn is_lost_for(&self, player: Player, depth: u32) -> bool {
+        let attacker = player.next();
+        if self.result.is_over() {
+            return self.result.winner() == Some(attacker);
+        }
+        if depth == 0 {
+            return false;
+        }
+        let mut state = self.clone();
+        if self.current_player == player {
+            state.cannot_stop_road(attacker, depth + 1)
+        } else {
+            state.forces_road(attacker, depth)
+        }
+    }
+
+    /// Whether `attacker`, who is to move, has a turn that wins by road within
+    /// `plies` whatever the replies
+    fn forces_road(&mut self, attacker: Player, plies: u32) -> bool {
+        self.legal_turns().iter().any(|turn| {
+            let Ok(unmake) = self.make(turn) else {
+                return false;
+            };
+            let forced = match self.result {
+                GameResult::RoadWin(winner) => winner == attacker,
+                GameResult::Ongoing => plies >= 3 && self.cannot_stop_road(attacker, plies - 1),
+                _ => false,
+            };
+            self.unmake(unmake);
+            forced
+        })
+    }
+
+    /// Whether every turn the player to move has still lets `attacker` force a
+    /// road within `plies`
+    fn cannot_stop_road(&mut self, attacker: Player, plies: u32) -> bool {
+        self.legal_turns().iter().all(|turn| {
+            let Ok(unmake) = self.make(turn) else {
+                return true;
+            };
+            let lost = match self.result {
+                // Including a road of the attacker's that the reply completes
+                GameResult::RoadWin(winner) => winner == attacker,
+                GameResult::Ongoing => self.forces_road(attacker, plies - 1),
+                _ => false,
+            };
+            self.unmake(unmake);
+            lost
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Turn;
+
+    #[test]
+    fn finds_the_shortest_forced_road() {
+        // Tak: e3 finishes White's road
+        let tak = GameState::from_tps("x5/x4,2/1,1,1,1,x/x5/2,2,2,x2 1 5").unwrap();
+        assert_eq!(tak.tinue_depth(1), Some(1));
+        assert_eq!(tak.tinue_depth(5), Some(1));
+
+        // d2 threatens both e2 and d3, and Black can only block one of them
+        let fork = GameState::from_tps("2,2,x3/2,2,x3/x4,1/1,1,1,x2/x5 1 5").unwrap();
+        assert_eq!(fork.tinue_depth(1), None);
+        assert_eq!(fork.tinue_depth(2), None);
+        assert_eq!(fork.tinue_depth(3), Some(3));
+
+        // Black has nothing coming
+        let mut quiet = fork.clone();
+        assert!(quiet.apply_turn(&Turn::from_ptn("Sd2", Player::White, 5).unwrap()));
+        assert_eq!(quiet.tinue_depth(3), None);
+    }
+
+    #[test]
+    fn lost_when_every_turn_allows_a_road() {
+        // After d2 Black can only block one of White's two roads
+        let forked = GameState::from_tps("2,2,x3/2,2,x3/x4,1/1,1,1,1,x/x5 2 5").unwrap();
+        assert!(!forked.is_lost_for(Player::Black, 0));
+        assert!(forked.is_lost_for(Player::Black, 1));
+        assert!(!forked.is_lost_for(Player::White, 1));
+
+        // Black blocks e3 and White has nothing else straight away
+        let tak = GameState::from_tps("x5/x4,2/1,1,1,1,x/x5/2,2,2,x2 2 5").unwrap();
+        assert!(!tak.is_lost_for(Player::Black, 1));
+        // It's White's turn after Black's, with the road still open
+        let mut ignored = tak.clone();
+        assert!(ignored.apply_turn(&Turn::from_ptn("a5", Player::Black, 5).unwrap()));
+        assert!(ignored.is_lost_for(Player::Black, 1));
+    }
+}
@@ -0,0 +1,84 @@
+//! Zobrist hashing of positions, so that searches can recognise a position
+//! they have already seen, however it was reached.
+//!
+//! Every stone at every height of every square has its own pseudo-random key,
+//! and a position's hash is the XOR of the keys of its stones, plus one more
+//! key if Black is to move.
+
+use super::{GameState, Loc, Player, Stone, StoneType};
+
+/// Mixes `x` into a well distributed 64 bit value (the SplitMix64 finaliser)
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The key of `stone` at `height` (0 being the bottom) of the square at `index`
+fn stone_key(index: usize, height: usize, stone: Stone) -> u64 {
+    let owner = match stone.owner {
+        Player::White => 0,
+        Player::Black => 1,
+    };
+    let typ = match stone.typ {
+        StoneType::Flat => 0,
+        StoneType::Standing => 1,
+        StoneType::Capstone => 2,
+    };
+    let id = ((index as u64) << 16 | height as u64) << 8 | (owner * 3 + typ);
+    mix(id)
+}
+
+const BLACK_TO_MOVE: u64 = 0x2545_f491_4f6c_dd1d;
+
+impl GameState {
+    /// A hash of the stones on the board and the player to move. Equal
+    /// positions always have the same hash, different ones almost never do.
+    pub fn zobrist(&self) -> u64 {
+        let size = self.board.size();
+        let mut hash = match self.current_player {
+            Player::White => 0,
+            Player::Black => BLACK_TO_MOVE,
+        };
+        for index in 0..size * size {
+            for (height, stone) in self.board[Loc::from_index(index, size)].iter().enumerate() {
+                hash ^= stone_key(index, height, *stone);
+            }
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transpositions_hash_equal() {
+        // The opening swap colours the first two stones, so keep them fixed
+        let a = GameState::from_ptn_moves(5, 0, &["a1", "e5", "b2", "d4", "c2", "c4"]).unwrap();
+        let b = GameState::from_ptn_moves(5, 0, &["a1", "e5", "c2", "c4", "b2", "d4"]).unwrap();
+        assert_eq!(a.zobrist(), b.zobrist());
+
+        let c = GameState::from_ptn_moves(5, 0, &["a1", "e5", "b2", "d4", "c2", "Sc4"]).unwrap();
+        assert_ne!(a.zobrist(), c.zobrist());
+        let d = GameState::from_ptn_moves(5, 0, &["a1", "e5", "b2", "d4", "c2"]).unwrap();
+        assert_ne!(a.zobrist(), d.zobrist());
+        assert_ne!(GameState::new(5).zobrist(), d.zobrist());
+    }
+
+    #[test]
+    fn side_to_move_changes_hash() {
+        let white = GameState::from_tps("x5/x5/x2,1,x2/x5/x5 1 3").unwrap();
+        let black = GameState::from_tps("x5/x5/x2,1,x2/x5/x5 2 3").unwrap();
+        assert_ne!(white.zobrist(), black.zobrist());
+    }
+
+    #[test]
+    fn stack_order_changes_hash() {
+        let a = GameState::from_tps("x5/x5/x2,12,x2/x5/x5 1 3").unwrap();
+        let b = GameState::from_tps("x5/x5/x2,21,x2/x5/x5 1 3").unwrap();
+        assert_ne!(a.zobrist(), b.zobrist());
+    }
+}
@@ -0,0 +1,5 @@
+//! The rules and the bot, without anything from Bevy, so both the game and
+//! the `tak-cli` tool can use them.
+
+pub mod bot;
+pub mod engine;
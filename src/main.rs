@@ -1,12 +1,42 @@
-mod engine;
+mod clipboard;
 mod fixed_aspect_ratio;
+mod web_storage;
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use bevy::{
-    color::palettes::css::{BLACK, GREEN, GREY, RED, WHITE},
+    color::palettes::css::{BLACK, DARK_SLATE_GRAY, GOLD, GREY, SLATE_GRAY, TAN, WHITE},
     ecs::spawn::SpawnIter,
+    input::{
+        ButtonState,
+        keyboard::{Key, KeyboardInput},
+    },
     prelude::*,
+    ui::FocusPolicy,
+    window::PrimaryWindow,
 };
 use fixed_aspect_ratio::{FixedAspectRatio, FixedAspectRatioPlugin};
+use tak::{
+    bot::{EvalWeights, best_move},
+    engine::{
+        Board, Clock, Dir, GameResult, GameState, Loc, Player, SavedGame, Stone, StoneType, Turn,
+    },
+};
+use web_storage::WebStoragePlugin;
+
+/// Where "Save Game" writes to and "Load Game" reads from
+const SAVE_PATH: &str = "tak_save.json";
+
+/// How many turns ahead "Bot Move" looks
+const BOT_DEPTH: u32 = 2;
+
+/// Each player's time in a new game, and what they get back after each turn.
+/// Loaded games and positions aren't timed, since saves don't keep clocks.
+const CLOCK_TIME: Duration = Duration::from_secs(10 * 60);
+const CLOCK_INCREMENT: Duration = Duration::from_secs(5);
 
 fn main() {
     App::new()
@@ -20,12 +50,139 @@ fn main() {
         }))
         .add_plugins(FixedAspectRatioPlugin)
         .add_event::<MyButtonEvent>()
+        .add_event::<NotationSubmitted>()
+        .add_event::<GameCommand>()
+        .add_event::<GameChanged>()
+        .insert_resource(Game::new(timed(GameState::new(6))))
+        .add_plugins(WebStoragePlugin)
+        .init_resource::<StackView>()
+        .init_resource::<InfluenceOverlay>()
+        .init_resource::<AccessibleStones>()
+        .init_resource::<BoardStyle>()
+        .init_resource::<NotationInput>()
+        .init_resource::<NewGameStart>()
+        .init_resource::<Replay>()
+        .init_resource::<DirtySquares>()
+        .init_resource::<SpreadComposer>()
         .add_systems(Startup, setup)
-        .add_systems(Update, (generate_button_events, tile_interaction).chain())
+        .add_systems(
+            Update,
+            (
+                (
+                    generate_button_events,
+                    toggle_stack_view,
+                    toggle_influence_overlay,
+                    toggle_accessible_stones,
+                    toggle_grid_lines,
+                    focus_notation_input,
+                    compose_spread_keys,
+                ),
+                type_notation,
+                tick_clock,
+                (
+                    tile_interaction,
+                    toolbar_interaction,
+                    apply_notation,
+                    track_tooltip_hover,
+                ),
+                apply_game_commands,
+                reset_spread_composer,
+                sync_board_size,
+                take_dirty_squares,
+                handle_game_replaced,
+                (
+                    update_tiles,
+                    update_stone_rings,
+                    update_turn_text,
+                    update_spread_composer_text,
+                    update_carry_text,
+                    update_start_button,
+                    update_notation_text,
+                    update_tile_labels,
+                    highlight_lifted_stones,
+                    update_stack_tooltip,
+                    highlight_last_turn,
+                    spawn_crush_animation,
+                    apply_board_style,
+                    update_clock_text,
+                ),
+                animate_crush,
+            )
+                .chain(),
+        )
         .run();
 }
 
-fn setup(mut commands: Commands) {
+/// The game being played, and who is playing it
+#[derive(Resource, Deref, DerefMut)]
+struct Game {
+    #[deref]
+    state: GameState,
+    players: HashMap<Player, PlayerInfo>,
+    /// Counts the times the game was replaced by another, see
+    /// `handle_game_replaced`
+    generation: u64,
+}
+
+/// How a player is shown. The engine only knows them as White, who moves
+/// first, and Black.
+#[derive(Clone)]
+struct PlayerInfo {
+    /// Used in the UI and the PTN `Player1`/`Player2` tags
+    name: String,
+    /// Of their stones on the board
+    color: Color,
+}
+
+impl Game {
+    fn new(state: GameState) -> Self {
+        Game {
+            state,
+            players: HashMap::from([
+                (
+                    Player::White,
+                    PlayerInfo {
+                        name: "Player 1".to_owned(),
+                        color: WHITE.into(),
+                    },
+                ),
+                (
+                    Player::Black,
+                    PlayerInfo {
+                        name: "Player 2".to_owned(),
+                        color: BLACK.into(),
+                    },
+                ),
+            ]),
+            generation: 0,
+        }
+    }
+
+    /// Swaps in a different game, keeping the players
+    fn replace(&mut self, state: GameState) {
+        self.state = state;
+        self.generation += 1;
+    }
+
+    fn player(&self, player: Player) -> &PlayerInfo {
+        &self.players[&player]
+    }
+
+    /// The game as a PTN file, with the players' names in its tags
+    fn to_ptn(&self) -> String {
+        self.state.to_ptn_game(&[
+            ("Player1", &self.player(Player::White).name),
+            ("Player2", &self.player(Player::Black).name),
+        ])
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    game: Res<Game>,
+    style: Res<BoardStyle>,
+    mut changed: EventWriter<GameChanged>,
+) {
     commands.spawn((
         Camera2d,
         Projection::Orthographic(OrthographicProjection {
@@ -40,46 +197,383 @@ fn setup(mut commands: Commands) {
         Node {
             width: Val::Percent(100.),
             height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
             ..default()
         },
-        children![board(6)],
+        children![
+            toolbar(),
+            (
+                Name::new("Board Area"),
+                BoardArea,
+                Node {
+                    flex_grow: 1.,
+                    ..default()
+                },
+                children![board(game.board().size() as u16, &style)],
+            ),
+        ],
     ));
     // commands.spawn(board(6));
+    commands.spawn(stack_tooltip());
+    // So that everything drawn from the game is drawn for the first time
+    changed.write(GameChanged);
+}
+
+/// Parent of the board, which is replaced when a game of a different size is loaded
+#[derive(Component)]
+struct BoardArea;
+
+/// The board grid, which has `size` tiles to a side
+#[derive(Component)]
+struct BoardNode {
+    size: usize,
+}
+
+/// Colors and spacing of the board
+#[derive(Resource)]
+struct BoardStyle {
+    /// Behind everything, including the toolbar
+    window_background: Color,
+    /// Shows through the gaps between tiles
+    board_background: Color,
+    /// Width of the gaps between tiles, and around the edge of the board, as a
+    /// fraction of a tile's width
+    gap_ratio: f32,
+    /// Draws the gaps as grid lines of this color instead, `GRID_LINE_WIDTH`
+    /// pixels wide whatever the window size so they stay crisp
+    grid_lines: Option<Color>,
 }
 
-fn board(size: u16) -> impl Bundle {
+/// In logical pixels
+const GRID_LINE_WIDTH: f32 = 1.;
+
+impl Default for BoardStyle {
+    fn default() -> Self {
+        BoardStyle {
+            window_background: ClearColor::default().0,
+            board_background: Color::NONE,
+            gap_ratio: 0.05,
+            grid_lines: None,
+        }
+    }
+}
+
+impl BoardStyle {
+    /// Gaps on a board with `size` tiles to a side. Measured against the
+    /// window rather than the board's parent so that horizontal and vertical
+    /// gaps match, keeping the tiles square.
+    fn gap(&self, size: u16) -> Val {
+        match self.grid_lines {
+            Some(_) => Val::Px(GRID_LINE_WIDTH),
+            None => Val::VMin(100. * self.gap_ratio / f32::from(size)),
+        }
+    }
+
+    /// What shows through the gaps. The lines are just the board behind the
+    /// tiles, so they never get in the way of clicking one.
+    fn background(&self) -> Color {
+        self.grid_lines.unwrap_or(self.board_background)
+    }
+
+    fn apply(&self, node: &mut Node, size: u16) {
+        let gap = self.gap(size);
+        node.padding = UiRect::all(gap);
+        node.row_gap = gap;
+        node.column_gap = gap;
+    }
+}
+
+fn board(size: u16, style: &BoardStyle) -> impl Bundle {
+    let mut node = Node {
+        display: Display::Grid,
+        aspect_ratio: Some(1.),
+        grid_template_columns: vec![RepeatedGridTrack::flex(size, 1.)],
+        grid_template_rows: vec![RepeatedGridTrack::flex(size, 1.)],
+        ..default()
+    };
+    style.apply(&mut node, size);
     (
         Name::new("Board"),
-        Node {
-            display: Display::Grid,
-            padding: UiRect::all(Val::Px(5.)),
-            aspect_ratio: Some(1.),
-            grid_template_columns: vec![RepeatedGridTrack::flex(size, 1.)],
-            grid_template_rows: vec![RepeatedGridTrack::flex(size, 1.)],
-            row_gap: Val::Px(5.),
-            column_gap: Val::Px(5.),
-            ..default()
+        BoardNode {
+            size: usize::from(size),
         },
+        node,
+        BackgroundColor(style.background()),
         FixedAspectRatio,
-        Children::spawn(SpawnIter((0..size * size).map(|i| tile()))),
+        Children::spawn(SpawnIter(
+            (0..usize::from(size * size)).map(move |i| tile(Loc::from_index(i, size.into()))),
+        )),
     )
 }
 
 #[derive(Component)]
 struct Tile;
 
-fn tile() -> impl Bundle {
+/// Text on a tile describing its stack, as chosen by `StackView`
+#[derive(Component)]
+struct TileLabel;
+
+/// The end of a `TileLabel` listing the stones the `SpreadComposer` is
+/// picking up, or how many while stacks are hidden, so it's clear which ones
+/// will move
+#[derive(Component)]
+struct LiftedStones;
+
+/// The board square a `Tile` represents
+#[derive(Component)]
+struct TilePos(Loc);
+
+fn tile(loc: Loc) -> impl Bundle {
     (
         Name::new("Tile"),
         Tile,
+        TilePos(loc),
         Node {
             // border: UiRect::all(Val::Px(5.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
             ..default()
         },
         Button,
         MyButton::default(),
         BackgroundColor(WHITE.into()),
+        Outline::default(),
         // BorderColor(Color::BLACK),
+        children![(
+            TileLabel,
+            Text::default(),
+            TextColor(GREY.into()),
+            FocusPolicy::Pass,
+            Pickable::IGNORE,
+            children![(LiftedStones, TextSpan::default(), TextColor(GOLD.into()))],
+        )],
+    )
+}
+
+#[derive(Component, Clone, Copy)]
+enum ToolbarButton {
+    Save,
+    Load,
+    /// Copies the position being looked at as TPS
+    CopyTps,
+    /// Copies the game up to the position being looked at as PTN, with the
+    /// players' names
+    CopyPtn,
+    /// Picks what the next new game starts from
+    Start,
+    NewGame,
+    /// Lets the bot play for whoever is to move
+    BotMove,
+    /// Undoes a turn to look at an earlier position
+    StepBack,
+    /// Replays the turn undone by the last `StepBack`
+    StepForward,
+    /// Replays every undone turn, returning to the game being played
+    Latest,
+    /// Gives the game up for whoever is to move
+    Resign,
+}
+
+/// Turns undone by stepping back through the game, the next one to replay
+/// last. Turns can only be played from the latest position, when this is
+/// empty.
+#[derive(Resource, Default)]
+struct Replay {
+    undone: Vec<Turn>,
+}
+
+impl Replay {
+    fn is_live(&self) -> bool {
+        self.undone.is_empty()
+    }
+
+    /// `game` with every undone turn replayed. Undoing doesn't give back
+    /// time, so neither does replaying add increments, the clock is `game`'s.
+    fn live(&self, game: &GameState) -> GameState {
+        let mut live = game.clone();
+        for turn in self.undone.iter().rev() {
+            live.apply_turn(turn);
+        }
+        live.set_clock(game.clock());
+        live
+    }
+
+    /// Where `game` is in the game being played
+    fn position(&self, game: &GameState) -> String {
+        format!(
+            "Viewing ply {} of {}",
+            game.ply(),
+            game.ply() + self.undone.len()
+        )
+    }
+}
+
+/// Status shown when trying to play a turn while looking at an earlier position
+const NOT_LIVE: &str = "Go to the latest position to play";
+
+/// Built-in positions "New Game" can start from, by name and TPS
+const PUZZLES: &[(&str, &str)] = &[
+    ("Road in one", "x5/x4,2/1,1,1,1,x/x5/2,2,2,x2 1 5"),
+    ("Stop the road", "x5/x5/x5/2,2,2,x2/1,1,1,1,x 2 4"),
+];
+
+/// What "New Game" starts from
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+enum NewGameStart {
+    /// An empty board of this size
+    Empty(usize),
+    /// The position at this index in `PUZZLES`
+    Puzzle(usize),
+}
+
+impl Default for NewGameStart {
+    fn default() -> Self {
+        NewGameStart::Empty(6)
+    }
+}
+
+impl NewGameStart {
+    /// The next choice on the start button, going through the sizes and then
+    /// the puzzles
+    fn next(self) -> Self {
+        match self {
+            NewGameStart::Empty(8) => NewGameStart::Puzzle(0),
+            NewGameStart::Empty(size) => NewGameStart::Empty(size + 1),
+            NewGameStart::Puzzle(index) if index + 1 < PUZZLES.len() => {
+                NewGameStart::Puzzle(index + 1)
+            }
+            NewGameStart::Puzzle(_) => NewGameStart::Empty(3),
+        }
+    }
+
+    fn label(self) -> String {
+        match self {
+            NewGameStart::Empty(size) => format!("Size: {size}"),
+            NewGameStart::Puzzle(index) => format!("Puzzle: {}", PUZZLES[index].0),
+        }
+    }
+
+    /// The new game. Empty boards keep the rules of `previous` and are timed,
+    /// puzzles are played without a clock.
+    fn start(self, previous: &GameState) -> GameState {
+        match self {
+            NewGameStart::Empty(size) => timed(GameState::with_win_rules(
+                size,
+                previous.komi(),
+                previous.win_rules(),
+            )),
+            NewGameStart::Puzzle(index) => {
+                GameState::from_tps(PUZZLES[index].1).expect("built-in puzzles should be valid TPS")
+            }
+        }
+    }
+}
+
+/// Shows the outcome of the last action, e.g. errors from loading a game
+#[derive(Component)]
+struct StatusText;
+
+/// Shows whose turn it is, or how the game ended
+#[derive(Component)]
+struct TurnText;
+
+/// The box TPS positions and PTN moves are typed into
+#[derive(Component)]
+struct NotationBox;
+
+#[derive(Component)]
+struct NotationText;
+
+fn toolbar() -> impl Bundle {
+    (
+        Name::new("Toolbar"),
+        Node {
+            padding: UiRect::all(Val::Px(5.)),
+            column_gap: Val::Px(5.),
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        children![
+            file_buttons(),
+            toolbar_button(ToolbarButton::Start, ""),
+            toolbar_button(ToolbarButton::NewGame, "New Game"),
+            toolbar_button(ToolbarButton::BotMove, "Bot Move"),
+            replay_buttons(),
+            toolbar_button(ToolbarButton::Resign, "Resign"),
+            (TurnText, Text::default()),
+            (CarryText, Text::default()),
+            clocks(),
+            notation_box(),
+            (StatusText, Text::default()),
+        ],
+    )
+}
+
+/// The buttons for stepping through the game
+fn replay_buttons() -> impl Bundle {
+    (
+        Name::new("Replay Buttons"),
+        Node {
+            column_gap: Val::Px(5.),
+            ..default()
+        },
+        children![
+            toolbar_button(ToolbarButton::StepBack, "<"),
+            toolbar_button(ToolbarButton::StepForward, ">"),
+            toolbar_button(ToolbarButton::Latest, "Latest"),
+        ],
+    )
+}
+
+/// The buttons that take the game out of the app or bring one in
+fn file_buttons() -> impl Bundle {
+    (
+        Name::new("File Buttons"),
+        Node {
+            column_gap: Val::Px(5.),
+            ..default()
+        },
+        children![
+            toolbar_button(ToolbarButton::Save, "Save Game"),
+            toolbar_button(ToolbarButton::Load, "Load Game"),
+            toolbar_button(ToolbarButton::CopyTps, "Copy TPS"),
+            toolbar_button(ToolbarButton::CopyPtn, "Copy PTN"),
+        ],
+    )
+}
+
+fn notation_box() -> impl Bundle {
+    (
+        Name::new("Notation Box"),
+        NotationBox,
+        Node {
+            min_width: Val::Px(200.),
+            padding: UiRect::axes(Val::Px(10.), Val::Px(5.)),
+            ..default()
+        },
+        Button,
+        BackgroundColor(DARK_SLATE_GRAY.into()),
+        children![(
+            NotationText,
+            Text::default(),
+            FocusPolicy::Pass,
+            Pickable::IGNORE,
+        )],
+    )
+}
+
+fn toolbar_button(kind: ToolbarButton, label: &str) -> impl Bundle {
+    (
+        Name::new(label.to_owned()),
+        kind,
+        Node {
+            padding: UiRect::axes(Val::Px(10.), Val::Px(5.)),
+            ..default()
+        },
+        Button,
+        MyButton::default(),
+        BackgroundColor(DARK_SLATE_GRAY.into()),
+        children![(Text::new(label), FocusPolicy::Pass, Pickable::IGNORE)],
     )
 }
 
@@ -135,30 +629,1525 @@ fn generate_button_events(
     }
 }
 
+/// A change to the game, asked for by the player, the bot or the app. Systems
+/// send these rather than changing `Game` themselves, so that everything goes
+/// through `apply_game_commands` and is checked by the engine there.
+#[derive(Event, Debug, Clone)]
+enum GameCommand {
+    /// Places a stone for the player to move
+    PlaceRequest { loc: Loc, typ: StoneType },
+    /// Plays any turn, including spreads
+    MoveRequest(Turn),
+    /// Steps back a turn to look at an earlier position, see `Replay`
+    Undo,
+    /// Replays the turn undone by the last `Undo`
+    Redo,
+    /// Replays every undone turn, returning to the game being played
+    Latest,
+    /// The player to move gives up
+    Resign,
+    /// Replaces the game, e.g. with a new or loaded one
+    NewGame(GameState),
+}
+
+/// Sent whenever a `GameCommand` changed the game, for everything drawn from
+/// the game to redraw
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+struct GameChanged;
+
+/// Carries out every `GameCommand` sent this frame. Rejected commands change
+/// nothing and say why in the status text.
+fn apply_game_commands(
+    mut requests: EventReader<GameCommand>,
+    mut game: ResMut<Game>,
+    mut replay: ResMut<Replay>,
+    mut changed: EventWriter<GameChanged>,
+    mut status: Option<Single<&mut Text, With<StatusText>>>,
+) {
+    for command in requests.read() {
+        let message = match run_game_command(&mut game, &mut replay, command) {
+            Ok(message) => {
+                changed.write(GameChanged);
+                message
+            }
+            Err(err) => Some(err),
+        };
+        if let (Some(message), Some(status)) = (message, &mut status) {
+            status.0 = message;
+        }
+    }
+}
+
+/// Carries out `command`, returning what to show in the status text if
+/// anything, or why it can't be done
+fn run_game_command(
+    game: &mut Game,
+    replay: &mut Replay,
+    command: &GameCommand,
+) -> Result<Option<String>, String> {
+    let size = game.board().size();
+    match command {
+        GameCommand::PlaceRequest { .. } | GameCommand::MoveRequest(_) | GameCommand::Resign
+            if !replay.is_live() =>
+        {
+            Err(NOT_LIVE.to_owned())
+        }
+        GameCommand::PlaceRequest { loc, typ } => {
+            let turn = Turn::Place {
+                loc: *loc,
+                player: game.current_player(),
+                typ: *typ,
+            };
+            game.try_apply_turn(&turn)
+                .map_err(|err| format!("Can't play {}: {err}", turn.to_ptn(size)))?;
+            Ok(None)
+        }
+        GameCommand::MoveRequest(turn) => {
+            game.try_apply_turn(turn)
+                .map_err(|err| format!("Can't play {}: {err}", turn.to_ptn(size)))?;
+            Ok(None)
+        }
+        GameCommand::Undo => {
+            let played = game
+                .history()
+                .last()
+                .ok_or("No earlier position to step back to")?;
+            let turn = played.turn.clone();
+            game.undo();
+            replay.undone.push(turn);
+            Ok(Some(replay.position(game)))
+        }
+        GameCommand::Redo => {
+            let turn = replay
+                .undone
+                .pop()
+                .ok_or("Already at the latest position")?;
+            let clock = game.clock();
+            game.apply_turn(&turn);
+            game.set_clock(clock);
+            Ok(Some(replay.position(game)))
+        }
+        GameCommand::Latest => {
+            game.state = replay.live(game);
+            replay.undone.clear();
+            Ok(Some(String::new()))
+        }
+        GameCommand::Resign => {
+            let player = game.current_player();
+            game.resign()
+                .map_err(|err| format!("Can't resign: {err}"))?;
+            Ok(Some(format!("{} resigned", game.player(player).name)))
+        }
+        GameCommand::NewGame(state) => {
+            game.replace(state.clone());
+            replay.undone.clear();
+            Ok(None)
+        }
+    }
+}
+
+/// Clicking a tile places a flat for the current player, or goes to the
+/// `SpreadComposer` if it's their stack or a spread is being composed
 fn tile_interaction(
     mut events: EventReader<MyButtonEvent>,
-    mut query: Query<&mut BackgroundColor, With<Tile>>,
+    query: Query<&TilePos>,
+    game: Res<Game>,
+    mut composer: ResMut<SpreadComposer>,
+    mut requests: EventWriter<GameCommand>,
 ) {
     for event in events.read() {
-        if let Ok(mut background_color) = query.get_mut(event.entity) {
-            use MyButtonEventAction::*;
-            match event.action {
-                Hovered => {
-                    background_color.0 = GREY.into();
+        if !matches!(event.action, MyButtonEventAction::Clicked) {
+            continue;
+        }
+        let Ok(TilePos(loc)) = query.get(event.entity) else {
+            continue;
+        };
+        if !composer.click(*loc, &game) {
+            requests.write(GameCommand::PlaceRequest {
+                loc: *loc,
+                typ: StoneType::Flat,
+            });
+        }
+    }
+}
+
+/// A spread being put together a click at a time: first the stack to pick up
+/// from, then each square to drop on, clicking a square again to drop another
+/// stone there. Number keys change how many are picked up, Enter plays it and
+/// Escape (or clicking the stack again) cancels it.
+#[derive(Resource, Default, Debug, Clone, PartialEq, Eq)]
+struct SpreadComposer {
+    /// The stack being picked up from, `None` when nothing is being composed
+    source: Option<Loc>,
+    total: usize,
+    /// Set by the first drop
+    dir: Option<Dir>,
+    drops: Vec<usize>,
+}
+
+impl SpreadComposer {
+    /// Carries on composing with a click on `loc`, returning whether the
+    /// click was used for that rather than to place a stone
+    fn click(&mut self, loc: Loc, game: &GameState) -> bool {
+        let board = game.board();
+        let player = game.current_player();
+        let Some(source) = self.source else {
+            let total = board.max_pickup(loc, player, game.carry_limit());
+            if total == 0 {
+                return false;
+            }
+            *self = SpreadComposer {
+                source: Some(loc),
+                total,
+                dir: None,
+                drops: Vec::new(),
+            };
+            return true;
+        };
+        if loc == source {
+            *self = SpreadComposer::default();
+            return true;
+        }
+        if self.drops.iter().sum::<usize>() == self.total {
+            return true;
+        }
+        let last = match self.dir {
+            Some(dir) => source.move_in_by(dir, self.drops.len()),
+            None => source,
+        };
+        if loc == last {
+            *self.drops.last_mut().unwrap() += 1;
+            return true;
+        }
+        let next = Dir::ALL
+            .into_iter()
+            .filter(|dir| self.dir.is_none_or(|spreading| spreading == *dir))
+            .find(|dir| last.move_in(*dir) == loc);
+        if let Some(dir) = next {
+            self.dir = Some(dir);
+            self.drops.push(1);
+        }
+        true
+    }
+
+    /// How many stones from the top of the stack at `loc` are being picked up
+    fn lifted(&self, loc: Loc) -> usize {
+        if self.source == Some(loc) {
+            self.total
+        } else {
+            0
+        }
+    }
+
+    /// Picks up `total` stones instead, starting the drops again
+    fn set_total(&mut self, total: usize, game: &GameState) {
+        let Some(source) = self.source else {
+            return;
+        };
+        let board = game.board();
+        if (1..=board.max_pickup(source, game.current_player(), game.carry_limit()))
+            .contains(&total)
+        {
+            self.total = total;
+            self.dir = None;
+            self.drops.clear();
+        }
+    }
+
+    /// The spread so far, picking up only what has been dropped
+    fn turn(&self, player: Player) -> Option<Turn> {
+        Some(Turn::Move {
+            loc: self.source?,
+            player,
+            dir: self.dir?,
+            total: self.drops.iter().sum(),
+            drops: self.drops.clone(),
+        })
+    }
+
+    /// The spread in PTN and whether it can be played, for the status text
+    fn describe(&self, game: &GameState) -> String {
+        let Some(source) = self.source else {
+            return String::new();
+        };
+        let size = game.board().size();
+        let Some(turn) = self.turn(game.current_player()) else {
+            return format!(
+                "Spreading {} from {}: click where to drop them, Esc to cancel",
+                self.total,
+                source.to_algebraic(size)
+            );
+        };
+        let ptn = turn.to_ptn(size);
+        let left = self.total - self.drops.iter().sum::<usize>();
+        if !game.valid_turn(&turn) {
+            format!("{ptn} isn't legal, Esc to cancel")
+        } else if left > 0 {
+            format!("{ptn}: {left} left to drop")
+        } else {
+            format!("{ptn}: Enter to play it, Esc to cancel")
+        }
+    }
+}
+
+/// Number keys, Enter and Escape for the `SpreadComposer`, unless they are
+/// being typed into the `NotationBox`
+fn compose_spread_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    input: Res<NotationInput>,
+    game: Res<Game>,
+    mut composer: ResMut<SpreadComposer>,
+    mut requests: EventWriter<GameCommand>,
+) {
+    if input.focused || composer.source.is_none() {
+        return;
+    }
+    const DIGITS: [KeyCode; 8] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+    ];
+    for (i, key) in DIGITS.into_iter().enumerate() {
+        if keys.just_pressed(key) {
+            composer.set_total(i + 1, &game);
+        }
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        *composer = SpreadComposer::default();
+    }
+    let left = composer.total - composer.drops.iter().sum::<usize>();
+    if keys.just_pressed(KeyCode::Enter) && left == 0 {
+        if let Some(turn) = composer.turn(game.current_player()) {
+            requests.write(GameCommand::MoveRequest(turn));
+        }
+        *composer = SpreadComposer::default();
+    }
+}
+
+/// How many stones a spread can pick up, and how many the `SpreadComposer` is
+/// holding
+#[derive(Component)]
+struct CarryText;
+
+fn update_carry_text(
+    mut changed: EventReader<GameChanged>,
+    game: Res<Game>,
+    composer: Res<SpreadComposer>,
+    mut text: Single<&mut Text, With<CarryText>>,
+) {
+    if changed.is_empty() && !composer.is_changed() {
+        return;
+    }
+    changed.clear();
+    let limit = game.carry_limit();
+    text.0 = match composer.source {
+        Some(_) => format!("Carrying {}/{limit}", composer.total),
+        None => format!("Carry limit {limit}"),
+    };
+}
+
+/// Shows the spread being composed in the status text, clearing it when
+/// composing stops unless something else has been shown since
+fn update_spread_composer_text(
+    game: Res<Game>,
+    composer: Res<SpreadComposer>,
+    mut shown: Local<String>,
+    mut status: Single<&mut Text, With<StatusText>>,
+) {
+    if !composer.is_changed() {
+        return;
+    }
+    if composer.source.is_some() {
+        *shown = composer.describe(&game);
+        status.0.clone_from(&shown);
+    } else {
+        if status.0 == *shown {
+            status.0.clear();
+        }
+        shown.clear();
+    }
+}
+
+/// Whatever was being composed no longer fits a game that changed
+fn reset_spread_composer(
+    mut changed: EventReader<GameChanged>,
+    mut composer: ResMut<SpreadComposer>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    changed.clear();
+    if composer.source.is_some() {
+        *composer = SpreadComposer::default();
+    }
+}
+
+fn toolbar_interaction(
+    mut events: EventReader<MyButtonEvent>,
+    query: Query<&ToolbarButton>,
+    game: Res<Game>,
+    mut new_game_start: ResMut<NewGameStart>,
+    replay: Res<Replay>,
+    mut requests: EventWriter<GameCommand>,
+    mut status: Single<&mut Text, With<StatusText>>,
+) {
+    for event in events.read() {
+        if !matches!(event.action, MyButtonEventAction::Clicked) {
+            continue;
+        }
+        let Ok(button) = query.get(event.entity) else {
+            continue;
+        };
+        status.0 = match button {
+            ToolbarButton::Save => match save_game(&replay.live(&game)) {
+                Ok(()) => format!("Saved to {SAVE_PATH}"),
+                Err(err) => format!("Couldn't save game: {err}"),
+            },
+            ToolbarButton::Load => match load_game() {
+                Ok(loaded) => {
+                    requests.write(GameCommand::NewGame(loaded));
+                    format!("Loaded {SAVE_PATH}")
                 }
-                Unhovered => {
-                    background_color.0 = WHITE.into();
+                Err(err) => format!("Couldn't load game: {err}"),
+            },
+            ToolbarButton::CopyTps => match clipboard::copy(&game.to_tps()) {
+                Ok(()) => "Copied the position's TPS".to_owned(),
+                Err(err) => format!("Couldn't copy the position: {err}"),
+            },
+            ToolbarButton::CopyPtn => match clipboard::copy(&game.to_ptn()) {
+                Ok(()) => "Copied the game's PTN".to_owned(),
+                Err(err) => format!("Couldn't copy the game: {err}"),
+            },
+            ToolbarButton::Start => {
+                *new_game_start = new_game_start.next();
+                continue;
+            }
+            ToolbarButton::NewGame => {
+                requests.write(GameCommand::NewGame(new_game_start.start(&game)));
+                match *new_game_start {
+                    NewGameStart::Empty(size) => format!("Started a {size}x{size} game"),
+                    NewGameStart::Puzzle(index) => format!("Started \"{}\"", PUZZLES[index].0),
                 }
-                Pressed => {
-                    background_color.0 = BLACK.into();
+            }
+            ToolbarButton::BotMove if !replay.is_live() => NOT_LIVE.to_owned(),
+            ToolbarButton::BotMove => {
+                let size = game.board().size();
+                match best_move(&mut game.state.clone(), BOT_DEPTH, &EvalWeights::default()) {
+                    Some(turn) => {
+                        let mut ptn = turn.to_ptn(size);
+                        if game.board().would_crush(&turn).is_some() {
+                            ptn.push('*');
+                        }
+                        requests.write(GameCommand::MoveRequest(turn));
+                        format!("Bot played {ptn}")
+                    }
+                    None => "No moves to play".to_owned(),
+                }
+            }
+            ToolbarButton::StepBack => {
+                requests.write(GameCommand::Undo);
+                continue;
+            }
+            ToolbarButton::StepForward => {
+                requests.write(GameCommand::Redo);
+                continue;
+            }
+            ToolbarButton::Latest => {
+                requests.write(GameCommand::Latest);
+                continue;
+            }
+            ToolbarButton::Resign => {
+                requests.write(GameCommand::Resign);
+                continue;
+            }
+        };
+    }
+}
+
+/// Rebuilds the board when the game is replaced by one of a different size
+fn sync_board_size(
+    mut commands: Commands,
+    game: Res<Game>,
+    board_area: Single<Entity, With<BoardArea>>,
+    board_node: Single<(Entity, &BoardNode)>,
+    style: Res<BoardStyle>,
+) {
+    let (entity, BoardNode { size }) = *board_node;
+    if *size == game.board().size() {
+        return;
+    }
+    commands.entity(entity).despawn();
+    commands.spawn((
+        board(game.board().size() as u16, &style),
+        ChildOf(*board_area),
+    ));
+}
+
+fn update_start_button(
+    start: Res<NewGameStart>,
+    buttons: Query<(&ToolbarButton, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    if !start.is_changed() {
+        return;
+    }
+    for (button, children) in &buttons {
+        if !matches!(button, ToolbarButton::Start) {
+            continue;
+        }
+        let mut iter = texts.iter_many_mut(children);
+        while let Some(mut text) = iter.fetch_next() {
+            text.0 = start.label();
+        }
+    }
+}
+
+fn update_turn_text(
+    mut changed: EventReader<GameChanged>,
+    game: Res<Game>,
+    mut text: Single<&mut Text, With<TurnText>>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    changed.clear();
+    text.0 = match game.result() {
+        GameResult::Ongoing => {
+            let (white, black) = game.road_distances();
+            let needs = |player, distance: Option<u32>| {
+                let name = &game.player(player).name;
+                match distance {
+                    Some(distance) => format!("{name} needs {distance}"),
+                    None => format!("{name} is blocked"),
                 }
-                Released => {
-                    background_color.0 = RED.into();
+            };
+            // So a player isn't left wondering why nothing else works
+            let stuck = if game.legal_move_count() == 1 {
+                " Only 1 legal move."
+            } else {
+                ""
+            };
+            let lost = if game.is_lost_for(game.current_player(), 1) {
+                " No way to stop a road, resigning is an option."
+            } else {
+                ""
+            };
+            let last = game
+                .last_move_ptn()
+                .map(|ptn| format!(" Last move {ptn}."))
+                .unwrap_or_default();
+            format!(
+                "Move {}: {} to move.{last} {}, {}.{stuck}{lost}",
+                game.move_number(),
+                game.player(game.current_player()).name,
+                needs(Player::White, white),
+                needs(Player::Black, black)
+            )
+        }
+        result => {
+            let game: &Game = &game;
+            result.describe(game, |player| &game.player(player).name)
+        }
+    };
+}
+
+/// What has been typed into the `NotationBox`, and whether it takes keyboard
+/// input
+#[derive(Resource, Default)]
+struct NotationInput {
+    text: String,
+    focused: bool,
+}
+
+/// Enter was pressed in the `NotationBox`
+#[derive(Event)]
+struct NotationSubmitted(String);
+
+/// Clicking the box focuses it, clicking anywhere else unfocuses it
+fn focus_notation_input(
+    mouse: Res<ButtonInput<MouseButton>>,
+    notation_box: Single<&Interaction, With<NotationBox>>,
+    mut input: ResMut<NotationInput>,
+) {
+    if mouse.just_pressed(MouseButton::Left) {
+        input.focused = matches!(*notation_box, Interaction::Pressed);
+    }
+}
+
+fn type_notation(
+    mut keys: EventReader<KeyboardInput>,
+    mut input: ResMut<NotationInput>,
+    mut submitted: EventWriter<NotationSubmitted>,
+) {
+    for key in keys.read() {
+        if !(input.focused && key.state == ButtonState::Pressed) {
+            continue;
+        }
+        match &key.logical_key {
+            Key::Enter => {
+                let text = std::mem::take(&mut input.text);
+                submitted.write(NotationSubmitted(text));
+            }
+            Key::Backspace => {
+                input.text.pop();
+            }
+            Key::Escape => input.focused = false,
+            _ => {
+                if let Some(text) = &key.text {
+                    input
+                        .text
+                        .extend(text.chars().filter(|ch| !ch.is_control()));
                 }
-                Clicked => {
-                    background_color.0 = GREEN.into();
+            }
+        }
+    }
+}
+
+/// Loads typed TPS positions and plays typed PTN moves
+fn apply_notation(
+    mut events: EventReader<NotationSubmitted>,
+    game: Res<Game>,
+    mut requests: EventWriter<GameCommand>,
+    mut status: Single<&mut Text, With<StatusText>>,
+) {
+    for NotationSubmitted(text) in events.read() {
+        let text = text.trim();
+        let size = game.board().size();
+        // A whole position in TPS, a whole game as a PTN move list (which has
+        // move numbers, and might be drawn 1/2-1/2), or else a move in PTN to
+        // play
+        let parsed = if text.contains('/') && !text.contains('.') {
+            GameState::from_tps(text).map(GameCommand::NewGame)
+        } else if text.contains(char::is_whitespace) {
+            GameState::from_ptn_list(size, game.komi(), text).map(GameCommand::NewGame)
+        } else {
+            Turn::from_ptn(text, game.current_player(), size).map(GameCommand::MoveRequest)
+        };
+        match parsed {
+            Ok(command) => {
+                status.0 = String::new();
+                requests.write(command);
+            }
+            Err(err) => status.0 = format!("{text}: {err}"),
+        }
+    }
+}
+
+fn update_notation_text(
+    input: Res<NotationInput>,
+    mut text: Single<&mut Text, With<NotationText>>,
+    mut background_color: Single<&mut BackgroundColor, With<NotationBox>>,
+) {
+    if !input.is_changed() {
+        return;
+    }
+    text.0 = if input.focused {
+        format!("{}|", input.text)
+    } else if input.text.is_empty() {
+        "TPS or PTN".to_owned()
+    } else {
+        input.text.clone()
+    };
+    background_color.0 = if input.focused {
+        SLATE_GRAY.into()
+    } else {
+        DARK_SLATE_GRAY.into()
+    };
+}
+
+fn save_game(game: &GameState) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&game.to_saved()).map_err(|err| err.to_string())?;
+    std::fs::write(SAVE_PATH, json).map_err(|err| err.to_string())
+}
+
+fn load_game() -> Result<GameState, String> {
+    let json = std::fs::read_to_string(SAVE_PATH).map_err(|err| err.to_string())?;
+    let saved: SavedGame = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+    saved
+        .restore()
+        .ok_or_else(|| "the saved game is not a valid game".to_owned())
+}
+
+/// Squares whose stacks changed this frame, so only their tiles are redrawn
+#[derive(Resource, Default)]
+struct DirtySquares(HashSet<Loc>);
+
+/// Collects the squares the game changed since last frame. Doesn't count as
+/// changing the game, or everything watching it would update every frame.
+fn take_dirty_squares(mut game: ResMut<Game>, mut dirty: ResMut<DirtySquares>) {
+    dirty.0 = game
+        .bypass_change_detection()
+        .take_dirty()
+        .into_iter()
+        .collect();
+}
+
+/// Throws away whatever was in progress when the game is replaced outright,
+/// by `Game::replace` or by inserting a new `Game`, rather than played on.
+/// Undone turns and a half composed spread belong to the old game, and could
+/// be illegal or mean something else in the new one. Everything is redrawn,
+/// since the new game's squares weren't changed the way the UI expects.
+fn handle_game_replaced(
+    game: Res<Game>,
+    mut seen: Local<Option<u64>>,
+    mut replay: ResMut<Replay>,
+    mut composer: ResMut<SpreadComposer>,
+    mut dirty: ResMut<DirtySquares>,
+    mut changed: EventWriter<GameChanged>,
+) {
+    if !(game.is_added() || *seen != Some(game.generation)) {
+        return;
+    }
+    *seen = Some(game.generation);
+    replay.undone.clear();
+    *composer = SpreadComposer::default();
+    let size = game.board().size();
+    dirty.0 = (0..size * size)
+        .map(|index| Loc::from_index(index, size))
+        .collect();
+    changed.write(GameChanged);
+}
+
+/// Colors each tile by the owner of its top stone, tinted by who controls the
+/// square when the `InfluenceOverlay` is on. Only tiles that changed, or are
+/// hovered differently, are recolored unless the overlay is on.
+fn update_tiles(
+    game: Res<Game>,
+    dirty: Res<DirtySquares>,
+    overlay: Res<InfluenceOverlay>,
+    accessible: Res<AccessibleStones>,
+    mut query: Query<(&TilePos, Ref<Interaction>, &mut BackgroundColor), With<Tile>>,
+) {
+    let redraw_all = overlay.0 || overlay.is_changed() || accessible.is_changed();
+    let size = game.board().size();
+    let influence = overlay.0.then(|| game.board().influence_map());
+    let strongest = influence
+        .iter()
+        .flatten()
+        .map(|value| value.abs())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    for (TilePos(loc), interaction, mut background_color) in &mut query {
+        if !(redraw_all || dirty.0.contains(loc) || interaction.is_changed()) {
+            continue;
+        }
+        let empty: Color = if matches!(*interaction, Interaction::Hovered) {
+            GREY.into()
+        } else {
+            TAN.into()
+        };
+        let color = match game.board()[*loc].last() {
+            // Drawn by `update_stone_rings` instead
+            Some(stone) if accessible.is_ringed(stone) => empty,
+            Some(stone) => game.player(stone.owner).color,
+            None => empty,
+        };
+        background_color.0 = match &influence {
+            Some(influence) => {
+                let value = influence[loc.to_index(size)];
+                let toward = if value > 0 {
+                    Player::White
+                } else {
+                    Player::Black
+                };
+                color.mix(
+                    &game.player(toward).color,
+                    INFLUENCE_TINT * value.abs() as f32 / strongest as f32,
+                )
+            }
+            None => color,
+        };
+    }
+}
+
+/// How far the `InfluenceOverlay` tints the most contested square towards
+/// its controller's color, from 0 (not at all) to 1 (all the way)
+const INFLUENCE_TINT: f32 = 0.6;
+
+/// Whether tiles are tinted by `Board::influence_map`, to show which player
+/// dominates each part of the board
+#[derive(Resource, Default)]
+struct InfluenceOverlay(bool);
+
+/// Pressing I turns the `InfluenceOverlay` on and off, unless it is being
+/// typed into the `NotationBox`
+fn toggle_influence_overlay(
+    keys: Res<ButtonInput<KeyCode>>,
+    input: Res<NotationInput>,
+    mut overlay: ResMut<InfluenceOverlay>,
+) {
+    if keys.just_pressed(KeyCode::KeyI) && !input.focused {
+        overlay.0 = !overlay.0;
+    }
+}
+
+/// Width of the ring Black's stones are drawn as under `AccessibleStones`, as
+/// a fraction of a tile's width
+const ACCESSIBLE_RING: f32 = 0.15;
+
+/// Whether players' stones differ by more than color: Black's are drawn as a
+/// ring instead of a filled tile, and every stone is labelled with its TPS,
+/// e.g. `1S` for a White wall, so walls and capstones can be told apart too
+#[derive(Resource, Default)]
+struct AccessibleStones(bool);
+
+impl AccessibleStones {
+    fn is_ringed(&self, stone: &Stone) -> bool {
+        self.0 && stone.owner == Player::Black
+    }
+}
+
+/// Draws the rings of `AccessibleStones` as the borders of their tiles
+fn update_stone_rings(
+    game: Res<Game>,
+    dirty: Res<DirtySquares>,
+    accessible: Res<AccessibleStones>,
+    mut tiles: Query<(&TilePos, &mut Node, &mut BorderColor), With<Tile>>,
+) {
+    let size = game.board().size();
+    for (TilePos(loc), mut node, mut border_color) in &mut tiles {
+        if !(accessible.is_changed() || dirty.0.contains(loc)) {
+            continue;
+        }
+        match game.board()[*loc].last() {
+            Some(stone) if accessible.is_ringed(stone) => {
+                node.border = UiRect::all(Val::VMin(100. * ACCESSIBLE_RING / size as f32));
+                border_color.0 = game.player(stone.owner).color;
+            }
+            _ => {
+                node.border = UiRect::ZERO;
+                border_color.0 = Color::NONE;
+            }
+        }
+    }
+}
+
+/// Pressing A turns `AccessibleStones` on and off, unless it is being typed
+/// into the `NotationBox`
+fn toggle_accessible_stones(
+    keys: Res<ButtonInput<KeyCode>>,
+    input: Res<NotationInput>,
+    mut accessible: ResMut<AccessibleStones>,
+) {
+    if keys.just_pressed(KeyCode::KeyA) && !input.focused {
+        accessible.0 = !accessible.0;
+    }
+}
+
+/// Pressing G switches between gaps and grid lines between the tiles, unless
+/// it is being typed into the `NotationBox`
+fn toggle_grid_lines(
+    keys: Res<ButtonInput<KeyCode>>,
+    input: Res<NotationInput>,
+    mut style: ResMut<BoardStyle>,
+) {
+    if keys.just_pressed(KeyCode::KeyG) && !input.focused {
+        style.grid_lines = match style.grid_lines {
+            Some(_) => None,
+            None => Some(GREY.into()),
+        };
+    }
+}
+
+/// Outlines the squares the last turn changed, so it's clear what the other
+/// player (or the bot) just did. Nothing is outlined before the first turn.
+fn highlight_last_turn(
+    mut changed: EventReader<GameChanged>,
+    game: Res<Game>,
+    style: Res<BoardStyle>,
+    added: Query<(), Added<Tile>>,
+    mut tiles: Query<(&TilePos, &mut Outline), With<Tile>>,
+) {
+    if changed.is_empty() && added.is_empty() {
+        return;
+    }
+    changed.clear();
+    let squares = game
+        .history()
+        .last()
+        .map(|played| played.turn.squares())
+        .unwrap_or_default();
+    // Half the gap, so the outlines of neighbouring tiles don't overlap
+    let width = Val::VMin(50. * style.gap_ratio / game.board().size() as f32);
+    for (TilePos(loc), mut outline) in &mut tiles {
+        *outline = if squares.contains(loc) {
+            Outline::new(width, Val::ZERO, GOLD.into())
+        } else {
+            Outline::default()
+        };
+    }
+}
+
+/// Restyles the window and board whenever `BoardStyle` changes
+fn apply_board_style(
+    style: Res<BoardStyle>,
+    game: Res<Game>,
+    mut clear_color: ResMut<ClearColor>,
+    mut boards: Query<(&mut Node, &mut BackgroundColor), With<BoardNode>>,
+) {
+    if !style.is_changed() {
+        return;
+    }
+    clear_color.0 = style.window_background;
+    for (mut node, mut background_color) in &mut boards {
+        style.apply(&mut node, game.board().size() as u16);
+        background_color.0 = style.background();
+    }
+}
+
+/// How much of each stack the board shows. Only affects what is drawn, the
+/// game always uses the full stacks.
+#[derive(Resource, Default, Clone, Copy)]
+enum StackView {
+    /// Only the top stone, with a badge giving the height of taller stacks.
+    /// For teaching, where buried stones have to be remembered.
+    #[default]
+    Hidden,
+    /// Every stone, bottom to top
+    Revealed,
+}
+
+impl StackView {
+    /// The bottom of `stack` left where it is when `lifted` stones are picked
+    /// up. Stones are only shown being lifted when the stack is revealed.
+    fn staying(self, stack: &[Stone], lifted: usize) -> &[Stone] {
+        match self {
+            StackView::Hidden => stack,
+            StackView::Revealed => &stack[..stack.len() - lifted.min(stack.len())],
+        }
+    }
+
+    /// What a `LiftedStones` span shows when `lifted` stones of `stack` are
+    /// picked up: the stones themselves when revealed, or just how many when
+    /// hidden. Bevy's default font only has ASCII, so `^` stands in for an
+    /// arrow.
+    fn lifting(self, stack: &[Stone], lifted: usize) -> String {
+        match self {
+            StackView::Hidden if lifted > 0 => format!(" {}^", lifted.min(stack.len())),
+            StackView::Hidden => String::new(),
+            StackView::Revealed => stack[self.staying(stack, lifted).len()..]
+                .iter()
+                .map(|stone| stone.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Pressing R switches between hiding and revealing buried stones, unless it
+/// is being typed into the `NotationBox`
+fn toggle_stack_view(
+    keys: Res<ButtonInput<KeyCode>>,
+    input: Res<NotationInput>,
+    mut view: ResMut<StackView>,
+) {
+    if keys.just_pressed(KeyCode::KeyR) && !input.focused {
+        *view = match *view {
+            StackView::Hidden => StackView::Revealed,
+            StackView::Revealed => StackView::Hidden,
+        };
+    }
+}
+
+/// Revealed stacks leave out the stones being picked up for a spread, which
+/// `highlight_lifted_stones` shows instead
+fn update_tile_labels(
+    game: Res<Game>,
+    dirty: Res<DirtySquares>,
+    view: Res<StackView>,
+    accessible: Res<AccessibleStones>,
+    composer: Res<SpreadComposer>,
+    tiles: Query<(&TilePos, &Children), With<Tile>>,
+    mut labels: Query<&mut Text, With<TileLabel>>,
+) {
+    for (TilePos(loc), children) in &tiles {
+        if !(view.is_changed()
+            || accessible.is_changed()
+            || composer.is_changed()
+            || dirty.0.contains(loc))
+        {
+            continue;
+        }
+        let staying = view.staying(&game.board()[*loc], composer.lifted(*loc));
+        let height = game.board().stack_height(*loc);
+        let label = match (&*view, game.board()[*loc].last()) {
+            (StackView::Hidden, Some(top)) if accessible.0 && height > 1 => {
+                format!("{top} ({height})")
+            }
+            (StackView::Hidden, Some(top)) if accessible.0 => top.to_string(),
+            (StackView::Hidden, _) if height > 1 => height.to_string(),
+            (StackView::Hidden, _) => String::new(),
+            (StackView::Revealed, _) => staying.iter().map(|stone| stone.to_string()).collect(),
+        };
+        let mut iter = labels.iter_many_mut(children);
+        while let Some(mut text) = iter.fetch_next() {
+            // Only touch the text when it changes, so it isn't laid out again every frame
+            if text.0 != label {
+                text.0 = label.clone();
+            }
+        }
+    }
+}
+
+/// Fills in the `LiftedStones` of every tile whenever the spread being
+/// composed changes, emptying them when it moves to another stack or is
+/// played or cancelled
+fn highlight_lifted_stones(
+    game: Res<Game>,
+    view: Res<StackView>,
+    composer: Res<SpreadComposer>,
+    tiles: Query<(&TilePos, &Children), With<Tile>>,
+    labels: Query<&Children, With<TileLabel>>,
+    mut spans: Query<&mut TextSpan, With<LiftedStones>>,
+) {
+    if !(composer.is_changed() || view.is_changed()) {
+        return;
+    }
+    for (TilePos(loc), children) in &tiles {
+        let lifting = view.lifting(&game.board()[*loc], composer.lifted(*loc));
+        for label_children in labels.iter_many(children) {
+            let mut iter = spans.iter_many_mut(label_children);
+            while let Some(mut span) = iter.fetch_next() {
+                if span.0 != lifting {
+                    span.0 = lifting.clone();
                 }
             }
         }
     }
 }
+
+/// Lists the stones of the hovered tile's stack, next to the pointer
+#[derive(Component, Default)]
+struct StackTooltip {
+    /// The hovered tile, if any
+    loc: Option<Loc>,
+}
+
+fn stack_tooltip() -> impl Bundle {
+    (
+        Name::new("Stack Tooltip"),
+        StackTooltip::default(),
+        Node {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            padding: UiRect::axes(Val::Px(10.), Val::Px(5.)),
+            ..default()
+        },
+        Text::default(),
+        BackgroundColor(DARK_SLATE_GRAY.into()),
+        GlobalZIndex(1),
+        // Hovering the tooltip itself shouldn't unhover the tile under it
+        FocusPolicy::Pass,
+        Pickable::IGNORE,
+    )
+}
+
+/// Points the `StackTooltip` at whichever tile the pointer is over
+fn track_tooltip_hover(
+    mut events: EventReader<MyButtonEvent>,
+    tiles: Query<&TilePos>,
+    mut tooltip: Single<&mut StackTooltip>,
+) {
+    for event in events.read() {
+        let Ok(TilePos(loc)) = tiles.get(event.entity) else {
+            continue;
+        };
+        match event.action {
+            MyButtonEventAction::Hovered => tooltip.loc = Some(*loc),
+            MyButtonEventAction::Unhovered if tooltip.loc == Some(*loc) => tooltip.loc = None,
+            _ => {}
+        }
+    }
+}
+
+/// One line per stone at `loc`, bottom to top, or `None` if the square is
+/// empty
+fn stack_description(board: &Board, loc: Loc) -> Option<String> {
+    let lines: Vec<String> = board
+        .stack_owners(loc)
+        .into_iter()
+        .zip(board.stack_types(loc))
+        .enumerate()
+        .map(|(height, (owner, typ))| {
+            let typ = match typ {
+                StoneType::Flat => "flat",
+                StoneType::Standing => "wall",
+                StoneType::Capstone => "capstone",
+            };
+            format!("{}: Player {owner} {typ}", height + 1)
+        })
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Shows the `StackTooltip` beside the pointer while a tile with stones is
+/// hovered, and hides it otherwise
+fn update_stack_tooltip(
+    game: Res<Game>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    tooltip: Single<(&StackTooltip, &mut Node, &mut Text)>,
+) {
+    let (tooltip, mut node, mut text) = tooltip.into_inner();
+    let description = tooltip
+        .loc
+        .and_then(|loc| stack_description(game.board(), loc));
+    let (Some(description), Some(cursor)) = (description, window.cursor_position()) else {
+        if node.display != Display::None {
+            node.display = Display::None;
+        }
+        return;
+    };
+    node.display = Display::Flex;
+    node.left = Val::Px(cursor.x + 16.);
+    node.top = Val::Px(cursor.y + 16.);
+    if text.0 != description {
+        text.0 = description;
+    }
+}
+
+/// How long a crushed wall takes to fall flat
+const CRUSH_SECONDS: f32 = 0.4;
+
+/// `state` with a fresh clock for each player
+fn timed(mut state: GameState) -> GameState {
+    state.set_clock(Some(Clock::new(CLOCK_TIME, CLOCK_INCREMENT)));
+    state
+}
+
+/// Shows how much time a player has left
+#[derive(Component)]
+struct ClockText(Player);
+
+fn clocks() -> impl Bundle {
+    (
+        Name::new("Clocks"),
+        Node {
+            column_gap: Val::Px(10.),
+            ..default()
+        },
+        children![
+            (ClockText(Player::White), Text::default()),
+            (ClockText(Player::Black), Text::default()),
+        ],
+    )
+}
+
+/// Runs the clock of the player to move, ending the game if they run out.
+/// Clocks stop while looking at an earlier position or watching a wall get
+/// crushed.
+fn tick_clock(
+    time: Res<Time>,
+    mut game: ResMut<Game>,
+    replay: Res<Replay>,
+    animations: Query<(), With<CrushAnimation>>,
+    mut changed: EventWriter<GameChanged>,
+) {
+    if !(replay.is_live() && animations.is_empty()) {
+        return;
+    }
+    // Only a timeout is worth redrawing for, not every tick
+    let result = game.result();
+    game.bypass_change_detection().tick(time.delta());
+    if game.result() != result {
+        changed.write(GameChanged);
+    }
+}
+
+/// Counts down both clocks every frame, highlighting the one that's running
+fn update_clock_text(game: Res<Game>, mut texts: Query<(&ClockText, &mut Text, &mut TextColor)>) {
+    for (ClockText(player), mut text, mut color) in &mut texts {
+        let (label, new_color) = match game.clock() {
+            Some(clock) => {
+                // Rounded up, so the clock only reads 0:00 once it has run out
+                let secs = clock.remaining(*player).as_millis().div_ceil(1000);
+                let running = *player == game.current_player() && !game.result().is_over();
+                (
+                    format!("{}:{:02}", secs / 60, secs % 60),
+                    if running { GOLD } else { WHITE },
+                )
+            }
+            None => ("--:--".to_owned(), GREY),
+        };
+        // Only touch them when they change, so the text isn't laid out again
+        // every frame
+        if text.0 != label {
+            text.0 = label;
+        }
+        if color.0 != new_color.into() {
+            color.0 = new_color.into();
+        }
+    }
+}
+
+/// A wall falling flat after a capstone moved onto it
+#[derive(Component)]
+struct CrushAnimation {
+    timer: Timer,
+}
+
+/// Puts a `CrushAnimation` on the tile whose wall the last turn flattened
+fn spawn_crush_animation(
+    mut commands: Commands,
+    mut changed: EventReader<GameChanged>,
+    game: Res<Game>,
+    mut turns_seen: Local<usize>,
+    tiles: Query<(Entity, &TilePos), With<Tile>>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    changed.clear();
+    let turns = game.history().len();
+    let new_turn = turns == *turns_seen + 1;
+    *turns_seen = turns;
+    if !new_turn {
+        return;
+    }
+    let Some(loc) = game.last_crushed() else {
+        return;
+    };
+    // The capstone is on top, with the flattened wall right under it
+    let stack = &game.board()[loc];
+    let color = game.player(stack[stack.len() - 2].owner).color;
+    for (entity, TilePos(tile_loc)) in &tiles {
+        if *tile_loc != loc {
+            continue;
+        }
+        commands.spawn((
+            Name::new("Crushed Wall"),
+            CrushAnimation {
+                timer: Timer::from_seconds(CRUSH_SECONDS, TimerMode::Once),
+            },
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(40.),
+                top: Val::Percent(15.),
+                width: Val::Percent(20.),
+                height: Val::Percent(70.),
+                ..default()
+            },
+            BackgroundColor(color),
+            // Clicks and hovering should still reach the tile underneath
+            FocusPolicy::Pass,
+            Pickable::IGNORE,
+            ChildOf(entity),
+        ));
+    }
+}
+
+/// Tips crushed walls over and squashes them down, removing them once they lie flat
+fn animate_crush(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut CrushAnimation, &mut Transform)>,
+) {
+    for (entity, mut animation, mut transform) in &mut query {
+        animation.timer.tick(time.delta());
+        let progress = animation.timer.fraction();
+        transform.rotation = Quat::from_rotation_z(progress * std::f32::consts::FRAC_PI_2);
+        transform.scale = Vec3::new(1. - 0.5 * progress, 1., 1.);
+        if animation.timer.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ptn_tags_use_player_names() {
+        let mut game = Game::new(GameState::from_ptn_moves(5, 0, &["a1", "e5"]).unwrap());
+        game.players.get_mut(&Player::White).unwrap().name = "Ann".to_owned();
+        game.players.get_mut(&Player::Black).unwrap().name = "Ben".to_owned();
+        let ptn = game.to_ptn();
+        assert!(
+            ptn.contains("[Player1 \"Ann\"]\n[Player2 \"Ben\"]\n"),
+            "{ptn}"
+        );
+        assert!(ptn.ends_with("1. a1 e5\n"), "{ptn}");
+    }
+
+    #[test]
+    fn replay_returns_to_live_game() {
+        let live = GameState::from_ptn_moves(5, 0, &["a1", "e5", "Cc3", "d3", "c3+"]).unwrap();
+        let mut game = live.clone();
+        let mut replay = Replay::default();
+        for _ in 0..3 {
+            replay
+                .undone
+                .push(game.history().last().unwrap().turn.clone());
+            assert!(game.undo());
+        }
+        assert!(!replay.is_live());
+        assert_eq!(replay.position(&game), "Viewing ply 2 of 5");
+        assert_eq!(replay.live(&game), live);
+    }
+
+    #[test]
+    fn clock_flags_the_player_to_move() {
+        let mut app = App::new();
+        let mut state = GameState::new(5);
+        state.set_clock(Some(Clock::new(Duration::from_secs(1), Duration::ZERO)));
+        app.insert_resource(Game::new(state))
+            .init_resource::<Replay>()
+            .init_resource::<Time>()
+            .add_event::<GameChanged>()
+            .add_systems(Update, tick_clock);
+
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_millis(600));
+        app.update();
+        assert_eq!(app.world().resource::<Game>().result(), GameResult::Ongoing);
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_millis(600));
+        app.update();
+        let game = app.world().resource::<Game>();
+        assert_eq!(game.result(), GameResult::TimeoutWin(Player::Black));
+        assert_eq!(
+            game.clock().unwrap().remaining(Player::Black),
+            Duration::from_secs(1)
+        );
+        assert_eq!(app.world().resource::<Events<GameChanged>>().len(), 1);
+    }
+
+    #[test]
+    fn clock_text_is_left_alone_while_it_reads_the_same() {
+        #[derive(Resource, Default)]
+        struct Changes(usize);
+
+        fn count_changes(texts: Query<(Ref<Text>, Ref<TextColor>)>, mut changes: ResMut<Changes>) {
+            changes.0 += texts
+                .iter()
+                .filter(|(text, color)| text.is_changed() || color.is_changed())
+                .count();
+        }
+
+        let mut app = App::new();
+        let mut state = GameState::new(5);
+        state.set_clock(Some(Clock::new(Duration::from_secs(90), Duration::ZERO)));
+        app.insert_resource(Game::new(state))
+            .init_resource::<Changes>()
+            .add_systems(Update, (update_clock_text, count_changes).chain());
+        let clock = app
+            .world_mut()
+            .spawn((
+                ClockText(Player::White),
+                Text::default(),
+                TextColor::default(),
+            ))
+            .id();
+
+        app.update();
+        assert_eq!(app.world().get::<Text>(clock).unwrap().0, "1:30");
+        assert_eq!(app.world().get::<TextColor>(clock).unwrap().0, GOLD.into());
+        assert_eq!(app.world().resource::<Changes>().0, 1);
+        app.update();
+        assert_eq!(app.world().resource::<Changes>().0, 1);
+    }
+
+    #[test]
+    fn only_legal_commands_change_the_game() {
+        let mut app = App::new();
+        let state = GameState::from_ptn_moves(5, 0, &["a1", "e5"]).unwrap();
+        app.insert_resource(Game::new(state))
+            .init_resource::<Replay>()
+            .add_event::<GameCommand>()
+            .add_event::<GameChanged>()
+            .add_systems(Update, apply_game_commands);
+        let a1 = Loc::from_algebraic("a1", 5).unwrap();
+        let b1 = Loc::from_algebraic("b1", 5).unwrap();
+
+        app.world_mut().send_event(GameCommand::PlaceRequest {
+            loc: a1,
+            typ: StoneType::Flat,
+        });
+        app.update();
+        assert_eq!(app.world().resource::<Events<GameChanged>>().len(), 0);
+        assert_eq!(app.world().resource::<Game>().ply(), 2);
+
+        app.world_mut().send_event(GameCommand::PlaceRequest {
+            loc: b1,
+            typ: StoneType::Flat,
+        });
+        app.update();
+        assert_eq!(app.world().resource::<Events<GameChanged>>().len(), 1);
+        assert_eq!(app.world().resource::<Game>().ply(), 3);
+    }
+
+    #[test]
+    fn spread_composer_builds_a_spread() {
+        let game = GameState::from_tps("x5/x5/x2,21,x2/x5/x5 1 3").unwrap();
+        let square = |name| Loc::from_algebraic(name, 5).unwrap();
+        let mut composer = SpreadComposer::default();
+        // Empty squares are left for placing
+        assert!(!composer.click(square("a1"), &game));
+        assert!(composer.click(square("c3"), &game));
+        assert_eq!(composer.total, 2);
+        assert_eq!(composer.lifted(square("c3")), 2);
+        assert_eq!(composer.lifted(square("d3")), 0);
+        assert!(composer.describe(&game).starts_with("Spreading 2 from c3"));
+
+        for name in ["d3", "e3", "e3"] {
+            assert!(composer.click(square(name), &game));
+        }
+        assert_eq!(composer.drops, [1, 1]);
+        let turn = composer.turn(Player::White).unwrap();
+        assert_eq!(turn.to_ptn(5), "2c3>11");
+        assert!(game.valid_turn(&turn));
+        assert_eq!(
+            composer.describe(&game),
+            "2c3>11: Enter to play it, Esc to cancel"
+        );
+
+        // Picking up less starts the drops again, in any direction
+        composer.set_total(1, &game);
+        assert!(composer.drops.is_empty());
+        assert_eq!(composer.lifted(square("c3")), 1);
+        let c3 = &game.board()[square("c3")];
+        assert_eq!(StackView::Revealed.staying(c3, 1), &c3[..1]);
+        assert_eq!(StackView::Hidden.staying(c3, 1), c3);
+        assert_eq!(StackView::Revealed.lifting(c3, 1), c3[1].to_string());
+        assert_eq!(StackView::Hidden.lifting(c3, 1), " 1^");
+        assert_eq!(StackView::Hidden.lifting(c3, 0), "");
+        assert!(composer.click(square("c2"), &game));
+        assert!(composer.click(square("b2"), &game));
+        assert_eq!(composer.turn(Player::White).unwrap().to_ptn(5), "c3-");
+        composer.set_total(3, &game);
+        assert_eq!(composer.total, 1);
+
+        // Clicking the stack again cancels
+        assert!(composer.click(square("c3"), &game));
+        assert_eq!(composer, SpreadComposer::default());
+        assert_eq!(composer.lifted(square("c3")), 0);
+    }
+
+    #[test]
+    fn replacing_the_game_drops_what_was_in_progress() {
+        let mut app = App::new();
+        let state = GameState::from_tps("x5/x5/x2,21,x2/x5/x5 1 3").unwrap();
+        app.insert_resource(Game::new(state.clone()))
+            .init_resource::<Replay>()
+            .init_resource::<SpreadComposer>()
+            .init_resource::<DirtySquares>()
+            .add_event::<GameChanged>()
+            .add_systems(Update, handle_game_replaced);
+        app.update();
+        let c3 = Loc::from_algebraic("c3", 5).unwrap();
+        let mut composer = SpreadComposer::default();
+        assert!(composer.click(c3, &state));
+        app.insert_resource(composer);
+        app.world_mut()
+            .resource_mut::<Replay>()
+            .undone
+            .push(Turn::from_ptn("a1", Player::White, 5).unwrap());
+
+        // Playing on keeps them
+        let e1 = Turn::from_ptn("e1", Player::White, 5).unwrap();
+        assert!(app.world_mut().resource_mut::<Game>().apply_turn(&e1));
+        app.update();
+        assert!(app.world().resource::<SpreadComposer>().source.is_some());
+        assert_eq!(app.world().resource::<Replay>().undone.len(), 1);
+
+        app.world_mut()
+            .resource_mut::<Game>()
+            .replace(GameState::new(5));
+        app.update();
+        assert_eq!(
+            *app.world().resource::<SpreadComposer>(),
+            SpreadComposer::default()
+        );
+        assert!(app.world().resource::<Replay>().undone.is_empty());
+        assert_eq!(app.world().resource::<DirtySquares>().0.len(), 25);
+    }
+
+    #[test]
+    fn every_start_choice_can_be_played() {
+        let previous = GameState::new(6);
+        let mut start = NewGameStart::default();
+        let mut seen = Vec::new();
+        while !seen.contains(&start) {
+            seen.push(start);
+            let game = start.start(&previous);
+            assert_eq!(game.result(), GameResult::Ongoing, "{}", start.label());
+            assert!(!game.legal_turns().is_empty(), "{}", start.label());
+            start = start.next();
+        }
+        assert_eq!(seen.len(), 6 + PUZZLES.len());
+
+        // Playing on from a puzzle's position
+        let mut game = NewGameStart::Puzzle(0).start(&previous);
+        let size = game.board().size();
+        let turn = Turn::from_ptn("e3", game.current_player(), size).unwrap();
+        assert!(game.apply_turn(&turn));
+        assert_eq!(game.result(), GameResult::RoadWin(Player::White));
+    }
+
+    #[test]
+    fn accessible_stones_ring_blacks_stones() {
+        let mut app = App::new();
+        let state = GameState::from_ptn_moves(5, 0, &["a1", "e5", "Sb1"]).unwrap();
+        app.insert_resource(Game::new(state))
+            .init_resource::<DirtySquares>()
+            .init_resource::<AccessibleStones>()
+            .add_systems(Update, update_stone_rings);
+        let tiles = ["a1", "e5", "b1"].map(|square| {
+            let loc = Loc::from_algebraic(square, 5).unwrap();
+            app.world_mut()
+                .spawn((Tile, TilePos(loc), Node::default()))
+                .id()
+        });
+        let borders = |app: &App| {
+            tiles.map(|tile| app.world().get::<Node>(tile).unwrap().border != UiRect::ZERO)
+        };
+
+        app.update();
+        assert_eq!(borders(&app), [false; 3]);
+        app.world_mut().resource_mut::<AccessibleStones>().0 = true;
+        app.update();
+        // White placed Black's first stone on a1
+        assert_eq!(borders(&app), [true, false, false]);
+        app.world_mut().resource_mut::<AccessibleStones>().0 = false;
+        app.update();
+        assert_eq!(borders(&app), [false; 3]);
+    }
+
+    #[test]
+    fn switching_size_replaces_every_tile() {
+        let mut app = App::new();
+        app.insert_resource(Game::new(GameState::new(6)))
+            .init_resource::<BoardStyle>()
+            .add_systems(Update, sync_board_size);
+        let style = BoardStyle::default();
+        app.world_mut()
+            .spawn((BoardArea, Node::default(), children![board(6, &style)]));
+
+        for size in [4, 8, 3, 6] {
+            app.world_mut()
+                .resource_mut::<Game>()
+                .replace(GameState::new(size));
+            app.update();
+            let world = app.world_mut();
+            assert_eq!(
+                world.query::<&BoardNode>().iter(world).count(),
+                1,
+                "exactly one board after switching to size {size}"
+            );
+            let mut locs: Vec<usize> = world
+                .query_filtered::<&TilePos, With<Tile>>()
+                .iter(world)
+                .map(|TilePos(loc)| loc.to_index(size))
+                .collect();
+            locs.sort();
+            assert_eq!(locs, (0..size * size).collect::<Vec<_>>());
+        }
+    }
+}
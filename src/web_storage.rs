@@ -0,0 +1,71 @@
+//! Keeps the game in the browser's `localStorage` on the web build, so that
+//! refreshing the page doesn't lose it. Does nothing on other platforms.
+//!
+//! The game is stored as the JSON of a `SavedGame` under `STORAGE_KEY`.
+
+use bevy::prelude::*;
+
+/// Must be added after the `Game` resource is inserted, whose game it
+/// replaces with the stored one if there is a valid one
+pub struct WebStoragePlugin;
+
+impl Plugin for WebStoragePlugin {
+    #[cfg(target_arch = "wasm32")]
+    fn build(&self, app: &mut App) {
+        if let Some(game) = wasm::load() {
+            app.world_mut().resource_mut::<crate::Game>().replace(game);
+        }
+        app.add_systems(Update, wasm::persist_game);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn build(&self, _app: &mut App) {}
+}
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use bevy::prelude::*;
+
+    use tak::engine::{GameState, SavedGame};
+
+    use crate::{Game, GameChanged};
+
+    /// The `localStorage` key the current game is stored under
+    pub const STORAGE_KEY: &str = "tak_game";
+
+    fn storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    /// The stored game, or `None` if there isn't one or it can't be read, in
+    /// which case a new game is started
+    pub fn load() -> Option<GameState> {
+        let json = storage()?.get_item(STORAGE_KEY).ok()??;
+        let restored = serde_json::from_str::<SavedGame>(&json)
+            .ok()
+            .and_then(|saved| saved.restore());
+        if restored.is_none() {
+            warn!("Ignoring the invalid game stored under {STORAGE_KEY}");
+        }
+        restored
+    }
+
+    /// Stores the game whenever it changes
+    pub fn persist_game(mut changed: EventReader<GameChanged>, game: Res<Game>) {
+        if changed.is_empty() {
+            return;
+        }
+        changed.clear();
+        let Some(storage) = storage() else {
+            return;
+        };
+        match serde_json::to_string(&game.to_saved()) {
+            Ok(json) => {
+                if storage.set_item(STORAGE_KEY, &json).is_err() {
+                    warn!("Couldn't store the game under {STORAGE_KEY}");
+                }
+            }
+            Err(err) => warn!("Couldn't serialize the game: {err}"),
+        }
+    }
+}
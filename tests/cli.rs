@@ -0,0 +1,73 @@
+//! Runs `tak-cli` the way a script would, with a game on stdin
+
+#![cfg(feature = "cli")]
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Whether `tak-cli` exited successfully, and its stdout and stderr
+fn tak_cli(args: &[&str], input: &str) -> (bool, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tak-cli"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn plays_moves_to_a_road() {
+    let (ok, stdout, _) = tak_cli(&["--size", "3"], "1. c3 a1\n2. a2 c2\n3. a3\n");
+    assert!(ok);
+    assert!(stdout.starts_with("to move: 2, ply: 5, result: RoadWin(White)\n"));
+    assert!(stdout.contains("3 | 1 . 2\n"), "{stdout}");
+    assert!(stdout.ends_with("\nresult: R-0\n"), "{stdout}");
+}
+
+#[test]
+fn suggests_a_move_from_a_position() {
+    let (ok, stdout, _) = tak_cli(&["--suggest"], "x5/x5/1,1,1,1,x/x5/2,2,2,x2 1 5\n");
+    assert!(ok);
+    assert!(
+        stdout.ends_with("result: ongoing\nsuggest: e3\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn komi_counts_for_a_position() {
+    // A full board, with 5 flats to White and 4 to Black
+    let tps = "1,2,1/2,1,2/1,2,1 2 5\n";
+    let (ok, stdout, _) = tak_cli(&[], tps);
+    assert!(ok);
+    assert!(stdout.ends_with("\nresult: F-0\n"), "{stdout}");
+    let (ok, stdout, _) = tak_cli(&["--komi", "4"], tps);
+    assert!(ok);
+    assert!(stdout.ends_with("\nresult: 0-F\n"), "{stdout}");
+}
+
+#[test]
+fn stops_at_an_illegal_move() {
+    let (ok, stdout, stderr) = tak_cli(&[], "a1 e5 a1\n");
+    assert!(!ok);
+    assert!(stdout.is_empty());
+    assert_eq!(
+        stderr,
+        "tak-cli: a1: not a legal move: the board doesn't allow it\n"
+    );
+}